@@ -1,11 +1,19 @@
 struct VertexIn {
-    @builtin(instance_index) instance_index: u32,
     @location(0) pos: vec3<f32>,
 };
 
+struct InstanceIn {
+    @location(1) block_position: vec3<f32>,
+    @location(2) size: vec2<f32>,
+    @location(3) face: u32,
+    @location(4) block_id: u32,
+};
+
 struct VertexOut {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>,
+    @location(0) light: f32,
+    @location(1) uv: vec2<f32>,
+    @location(2) @interpolate(flat) layer: u32,
 };
 
 struct CameraUniform {
@@ -15,101 +23,135 @@ struct CameraUniform {
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+@group(1) @binding(0)
+var block_textures: texture_2d_array<f32>;
+@group(1) @binding(1)
+var block_sampler: sampler;
+// texture array layer of each block face, indexed by block_id * 6 + face,
+// followed by the faces of missing blocks
+@group(1) @binding(2)
+var<storage, read> face_layers: array<u32>;
 
-const back_face_rotation = mat3x3<f32>(
+
+// one packed draw per chunk, see `PackedDraw`
+struct PackedDraw {
+    origin: vec3<i32>,
+    first_face: u32,
+};
+
+// see `PackedFace` for the layout of the faces
+@group(2) @binding(0)
+var<storage, read> packed_faces: array<u32>;
+@group(2) @binding(1)
+var<storage, read> packed_draws: array<PackedDraw>;
+
+
+// per side, in the order of `ShiftDirection`: front, back, left, right, top, bottom.
+// the face lies offset along its normal and spans along u for its width 
+// and along v for its height, see `face_axes`
+var<private> face_offsets: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(0.0, 0.0, 1.0),
+    vec3<f32>(0.0, 0.0, 0.0),
+    vec3<f32>(0.0, 0.0, 0.0),
+    vec3<f32>(1.0, 0.0, 0.0),
+    vec3<f32>(0.0, 1.0, 0.0),
+    vec3<f32>(0.0, 0.0, 0.0),
+);
+
+var<private> face_us: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>( 1.0, 0.0,  0.0),
     vec3<f32>(-1.0, 0.0,  0.0),
-    vec3<f32>( 0.0, 1.0,  0.0),
+    vec3<f32>( 0.0, 0.0,  1.0),
     vec3<f32>( 0.0, 0.0, -1.0),
+    vec3<f32>( 1.0, 0.0,  0.0),
+    vec3<f32>( 1.0, 0.0,  0.0),
 );
 
-const left_face_rotation = mat3x3<f32>(
-    vec3<f32>(0.0, 0.0, -1.0),
+var<private> face_vs: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
     vec3<f32>(0.0, 1.0,  0.0),
-    vec3<f32>(1.0, 0.0,  0.0),
+    vec3<f32>(0.0, 1.0,  0.0),
+    vec3<f32>(0.0, 1.0,  0.0),
+    vec3<f32>(0.0, 1.0,  0.0),
+    vec3<f32>(0.0, 0.0, -1.0),
+    vec3<f32>(0.0, 0.0,  1.0),
 );
 
-const right_face_rotation = mat3x3<f32>(
-    vec3<f32>( 0.0, 0.0, 1.0),
-    vec3<f32>( 0.0, 1.0, 0.0),
-    vec3<f32>(-1.0, 0.0, 0.0),
+var<private> face_normals: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>( 0.0,  0.0,  1.0),
+    vec3<f32>( 0.0,  0.0, -1.0),
+    vec3<f32>(-1.0,  0.0,  0.0),
+    vec3<f32>( 1.0,  0.0,  0.0),
+    vec3<f32>( 0.0,  1.0,  0.0),
+    vec3<f32>( 0.0, -1.0,  0.0),
 );
 
-const top_face_rotation = mat3x3<f32>(
-    vec3<f32>(1.0,  0.0,  0.0),
-    vec3<f32>(0.0,  0.0, -1.0),
-    vec3<f32>(0.0,  1.0,  0.0),
+// same as `QUAD_VERTICES`
+var<private> quad_corners: array<vec2<f32>, 4> = array<vec2<f32>, 4>(
+    vec2<f32>(0.0, 0.0),
+    vec2<f32>(1.0, 0.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(0.0, 1.0),
 );
 
-const bottom_face_rotation = mat3x3<f32>(
-    vec3<f32>(1.0,  0.0, 0.0),
-    vec3<f32>(0.0,  0.0, 1.0),
-    vec3<f32>(0.0, -1.0, 0.0),
-);
+const light_source = vec3<f32>(0.2, 1.0, 0.3);
 
 
-const front_face_normal = vec3<f32>(0.0, 0.0, 1.0);
-const back_face_normal = vec3<f32>(0.0, 0.0, -1.0);
-const left_face_normal = vec3<f32>(-1.0, 0.0, 0.0);
-const right_face_normal = vec3<f32>(1.0, 0.0, 0.0);
-const top_face_normal = vec3<f32>(0.0, 1.0, 0.0);
-const bottom_face_normal = vec3<f32>(0.0, -1.0, 0.0);
+// places the corner of a face covering size blocks, the block spans from its position to position + size
+fn face_vertex(block_position: vec3<f32>, corner: vec2<f32>, size: vec2<f32>, face: u32, block_id: u32) -> VertexOut {
+    let u = face_us[face];
+    let v = face_vs[face];
+    // faces along a negative axis start at the far end of the rectangle
+    let start = face_offsets[face] + max(-u, vec3<f32>(0.0)) * size.x + max(-v, vec3<f32>(0.0)) * size.y;
+    let position = block_position + start + u * corner.x * size.x + v * corner.y * size.y;
 
-const light_source = vec3<f32>(0.2, 1.0, 0.3);
+    let light_strength = (dot(face_normals[face], light_source) + 1.0) / 2.0;
 
+    var output: VertexOut;
+    output.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    output.light = min(light_strength + 0.1, 1.0);
+    // repeats once per block across the merged rectangle, 
+    // v points down the texture while y points up the face
+    output.uv = vec2<f32>(corner.x, 1.0 - corner.y) * size;
+    // ids missing from the registry use the last block, which has the missing texture
+    let block = min(block_id, arrayLength(&face_layers) / 6u - 1u);
+    output.layer = face_layers[block * 6u + face];
+
+    return output;
+}
 
 @vertex 
 fn vert(
     model: VertexIn,   
+    instance: InstanceIn,
 ) -> VertexOut {
-    let face_index = model.instance_index % 6u;
-    let cube_index = model.instance_index / 6u;
-
-    let x = f32(cube_index % 10u);
-    let z = f32(cube_index / 10u) % 10.0;
-    let y = f32(cube_index / 100u);
-
-    var position = model.pos - vec3<f32>(0.5, 0.5, -0.5);
-    var normal = front_face_normal;
-
-    if (face_index == 1u) {
-        position = back_face_rotation * position;  
-        normal = back_face_normal;
-    } else if (face_index == 2u) {
-        position = left_face_rotation * position;  
-        normal = left_face_normal;
-    } else if (face_index == 3u) {
-        position = right_face_rotation * position;  
-        normal = right_face_normal;
-    } else if (face_index == 4u) {
-        position = top_face_rotation * position;  
-        normal = top_face_normal;
-    } else if (face_index == 5u) {
-        position = bottom_face_rotation * position;  
-        normal = bottom_face_normal;
-    }
-
-    normal = normalize(normal);
-
-
-    let spacing = 1.0;
-    position += vec3<f32>(1.0 * spacing * x, 1.0 * spacing * y, 1.0 * spacing * z);
+    return face_vertex(instance.block_position, model.pos.xy, instance.size, instance.face, instance.block_id);
+}
 
-    var color = vec3(f32(face_index) / 10.0, 0.0, 0.0);
-    let light_strength = (dot(normal, light_source) + 1.0) / 2.0;
-    color.x = min(light_strength + 0.1, 1.0);
-    color.y = 0.1;
+// the vertex index is offset by 4 per drawn chunk, it selects the chunk and the corner of the quad
+@vertex
+fn packed_vert(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOut {
+    let draw = packed_draws[vertex_index / 4u];
+    let face = packed_faces[draw.first_face + instance_index];
 
-    var output: VertexOut;
-    output.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
-    output.color = color;
+    let local = vec3<i32>(vec3<u32>(face, face >> 4u, face >> 8u) & vec3<u32>(15u));
+    let block_position = vec3<f32>(draw.origin + local);
 
-    return output;
+    return face_vertex(block_position, quad_corners[vertex_index % 4u], vec2<f32>(1.0), (face >> 12u) & 7u, face >> 15u);
 }
 
 
 @fragment
 fn frag(in: VertexOut) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    let texel = textureSample(block_textures, block_sampler, in.uv, in.layer);
+    // cutout textures, like glass and leaves
+    if (texel.a < 0.5) {
+        discard;
+    }
+
+    return vec4<f32>(texel.rgb * in.light, 1.0);
 }
 
 @fragment 
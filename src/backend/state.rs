@@ -1,19 +1,75 @@
+/// Where the frames are drawn to.
+pub enum RenderTarget {
+    Surface(wgpu::Surface),
+    /// A texture of the state's size, read back with `HardwareState::read_frame`
+    Offscreen(wgpu::Texture),
+}
+
+enum FrameTexture<'a> {
+    Surface(wgpu::SurfaceTexture),
+    Offscreen(&'a wgpu::Texture),
+}
+
+/// The texture one frame is drawn into.
+pub struct Frame<'a> {
+    texture: FrameTexture<'a>,
+    view: wgpu::TextureView,
+}
+
+impl Frame<'_> {
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    /// Has the COPY_SRC usage.
+    pub fn texture(&self) -> &wgpu::Texture {
+        match &self.texture {
+            FrameTexture::Surface(surface_texture) => &surface_texture.texture,
+            FrameTexture::Offscreen(texture) => texture,
+        }
+    }
+
+    /// Shows the frame in the window, offscreen frames stay in their texture.
+    pub fn present(self) {
+        if let FrameTexture::Surface(surface_texture) = self.texture {
+            surface_texture.present();
+        }
+    }
+}
+
+
+
 pub struct HardwareState {
     adapter: wgpu::Adapter,
     device: wgpu::Device,
     queue: wgpu::Queue,
-    surface: wgpu::Surface,
+    target: RenderTarget,
     surface_format: wgpu::TextureFormat,
-    window: winit::window::Window,
+    frame_usage: wgpu::TextureUsages,
+    window: Option<winit::window::Window>,
+    size: winit::dpi::PhysicalSize<u32>,
 }
 
 impl HardwareState {
-    pub fn window(&self) -> &winit::window::Window {
-        &self.window
+    /// None for headless states.
+    pub fn window(&self) -> Option<&winit::window::Window> {
+        self.window.as_ref()
     }
 
-    pub fn surface(&self) -> &wgpu::Surface {
-        &self.surface
+    /// None for headless states.
+    pub fn surface(&self) -> Option<&wgpu::Surface> {
+        match &self.target {
+            RenderTarget::Surface(surface) => Some(surface),
+            RenderTarget::Offscreen(_) => None,
+        }
+    }
+
+    pub fn target(&self) -> &RenderTarget {
+        &self.target
+    }
+
+    pub fn adapter(&self) -> &wgpu::Adapter {
+        &self.adapter
     }
 
     pub fn device(&self) -> &wgpu::Device {
@@ -24,18 +80,42 @@ impl HardwareState {
         &self.queue
     }
 
+    /// Format of the frames, also used by headless states.
     pub fn surface_format(&self) -> &wgpu::TextureFormat {
         &self.surface_format
     }
+
+    /// Size of the frames in pixels.
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.window.is_none()
+    }
+
+    /// Whether the frames can be copied, which some surfaces do not support.
+    pub fn supports_frame_capture(&self) -> bool {
+        self.frame_usage.contains(wgpu::TextureUsages::COPY_SRC)
+    }
 }
 
 impl HardwareState {
+    pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Features used when the adapter supports them, the wireframe needs the polygon modes.
+    const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::POLYGON_MODE_LINE
+        .union(wgpu::Features::POLYGON_MODE_POINT)
+        .union(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        .union(wgpu::Features::MULTI_DRAW_INDIRECT)
+        .union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
     pub async fn new(window: winit::window::Window) -> Self {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { 
-            backends: wgpu::Backends::PRIMARY, 
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::PRIMARY,
             dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
         });
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let mut surface = unsafe { instance.create_surface(&window) }.unwrap();
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
@@ -44,46 +124,207 @@ impl HardwareState {
             })
             .await
             .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: Some("Device"),
-                    features: wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::POLYGON_MODE_POINT | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES, 
-                    limits: wgpu::Limits::default(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await.unwrap();
 
-        let surface_format = configure_surface(&device, &surface, &adapter, window.inner_size());
+        let size = window.inner_size();
+        let (surface_format, frame_usage) = configure_surface(&device, &mut surface, &adapter, size);
 
         Self {
             adapter,
             device,
             queue,
-            surface,
+            target: RenderTarget::Surface(surface),
             surface_format,
-            window,
+            frame_usage,
+            window: Some(window),
+            size,
+        }
+    }
+
+    /// Creates a state without a window, which renders into an offscreen texture of the given size.
+    /// Falls back to a software adapter when there is no GPU, returns None if there is no adapter at all.
+    pub async fn new_headless(size: winit::dpi::PhysicalSize<u32>) -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            dx12_shader_compiler: wgpu::Dx12Compiler::Fxc,
+        });
+
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+
+            if adapter.is_some() {
+                break;
+            }
         }
+
+        let adapter = adapter?;
+        log::info!("Headless rendering on {:?}", adapter.get_info());
+
+        let (device, queue) = match request_device(&adapter).await {
+            Ok(device) => device,
+            Err(error) => {
+                log::error!("Unable to request a device: {}", error);
+                return None;
+            },
+        };
+
+        let texture = create_offscreen_texture(&device, size);
+
+        Some(Self {
+            adapter,
+            device,
+            queue,
+            target: RenderTarget::Offscreen(texture),
+            surface_format: Self::OFFSCREEN_FORMAT,
+            frame_usage: OFFSCREEN_USAGE,
+            window: None,
+            size,
+        })
     }
 
     pub fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
-        self.surface_format = configure_surface(&self.device, &self.surface, &self.adapter, size);
+        self.size = size;
+
+        match &mut self.target {
+            RenderTarget::Surface(surface) => {
+                (self.surface_format, self.frame_usage) = configure_surface(&self.device, surface, &self.adapter, size);
+            },
+            RenderTarget::Offscreen(texture) => {
+                texture.destroy();
+                *texture = create_offscreen_texture(&self.device, size);
+            },
+        }
+    }
+
+    /// Returns the texture the next frame should be drawn into.
+    pub fn current_frame(&self) -> Result<Frame<'_>, wgpu::SurfaceError> {
+        match &self.target {
+            RenderTarget::Surface(surface) => {
+                let surface_texture = surface.get_current_texture()?;
+                let view = surface_texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+                Ok(Frame { texture: FrameTexture::Surface(surface_texture), view })
+            },
+            RenderTarget::Offscreen(texture) => Ok(Frame {
+                texture: FrameTexture::Offscreen(texture),
+                view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            }),
+        }
     }
 
+    /// Copies the last offscreen frame into RGBA bytes, row by row from the top left.
+    /// Returns None for states with a window and an error if the frame could not be read back.
+    pub fn read_frame(&self) -> Option<Result<Vec<u8>, wgpu::BufferAsyncError>> {
+        match &self.target {
+            RenderTarget::Surface(_) => None,
+            RenderTarget::Offscreen(texture) => Some(crate::read_texture_rgba(self, texture)),
+        }
+    }
 }
 
 
-fn configure_surface(device: &wgpu::Device, surface: &wgpu::Surface, adapter: &wgpu::Adapter, window_size: winit::dpi::PhysicalSize<u32>) -> wgpu::TextureFormat {
+async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    // software and GL adapters often fall short of the default limits
+    let limits = if wgpu::Limits::default().check_limits(&adapter.limits()) {
+        wgpu::Limits::default()
+    } else {
+        wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
+    };
+
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                features: adapter.features() & HardwareState::OPTIONAL_FEATURES,
+                limits,
+            },
+            None,
+        )
+        .await
+}
+
+const OFFSCREEN_USAGE: wgpu::TextureUsages = wgpu::TextureUsages::RENDER_ATTACHMENT.union(wgpu::TextureUsages::COPY_SRC);
+
+fn create_offscreen_texture(device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Texture"),
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HardwareState::OFFSCREEN_FORMAT,
+        usage: OFFSCREEN_USAGE,
+        view_formats: &[],
+    })
+}
+
+/// Texture usages the surface supports besides RENDER_ATTACHMENT. wgpu 0.16 leaves them out of `SurfaceCapabilities`
+/// and panics when configuring an unsupported usage, so they are asked from the backend.
+/// Backends that can not be asked support none.
+fn surface_usages(surface: &mut wgpu::Surface, adapter: &wgpu::Adapter) -> wgpu::TextureUsages {
+    macro_rules! hal_usages {
+        ($api:ty) => {
+            // the raw adapter and surface are only used to read the capabilities
+            unsafe {
+                adapter.as_hal::<$api, _, _>(|adapter| surface.as_hal_mut::<$api, _, _>(|surface| {
+                    Some(wgpu_hal::Adapter::surface_capabilities(adapter?, surface?)?.usage)
+                }))
+            }
+        };
+    }
+
+    let uses = match adapter.get_info().backend {
+        #[cfg(any(windows, all(unix, not(target_os = "ios"), not(target_os = "macos"))))]
+        wgpu::Backend::Vulkan => hal_usages!(wgpu_hal::api::Vulkan),
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        wgpu::Backend::Metal  => hal_usages!(wgpu_hal::api::Metal),
+        #[cfg(windows)]
+        wgpu::Backend::Dx12   => hal_usages!(wgpu_hal::api::Dx12),
+        #[cfg(windows)]
+        wgpu::Backend::Dx11   => hal_usages!(wgpu_hal::api::Dx11),
+        wgpu::Backend::Gl     => hal_usages!(wgpu_hal::api::Gles),
+        _ => None,
+    }.unwrap_or(wgpu_hal::TextureUses::empty());
+
+    let mut usages = wgpu::TextureUsages::empty();
+    usages.set(wgpu::TextureUsages::COPY_SRC, uses.contains(wgpu_hal::TextureUses::COPY_SRC));
+    usages.set(wgpu::TextureUsages::COPY_DST, uses.contains(wgpu_hal::TextureUses::COPY_DST));
+    usages
+}
+
+/// Returns the format and the usage of the frames.
+fn configure_surface(
+    device: &wgpu::Device,
+    surface: &mut wgpu::Surface,
+    adapter: &wgpu::Adapter,
+    window_size: winit::dpi::PhysicalSize<u32>,
+) -> (wgpu::TextureFormat, wgpu::TextureUsages) {
     let caps = surface.get_capabilities(adapter);
     let format = caps.formats
         .iter()
         .find(|format| format.is_srgb())
         .unwrap_or(&caps.formats[0]);
 
+    // COPY_SRC lets `Renderer::capture_next_frame` copy the presented frames
+    let copy_usages = wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC;
+    let usage = wgpu::TextureUsages::RENDER_ATTACHMENT | (copy_usages & surface_usages(surface, adapter));
+    if !usage.contains(wgpu::TextureUsages::COPY_SRC) {
+        log::warn!("The surface can not be copied from, frames can not be captured");
+    }
+
     let surface_config = wgpu::SurfaceConfiguration {
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
+        usage,
         format: *format,
         width: window_size.width,
         height: window_size.height,
@@ -92,6 +333,6 @@ fn configure_surface(device: &wgpu::Device, surface: &wgpu::Surface, adapter: &w
         view_formats: vec![],
     };
 
-    surface.configure(&device, &surface_config);
-    format.clone()
+    surface.configure(device, &surface_config);
+    (*format, usage)
 }
@@ -3,7 +3,9 @@ use std::ops::{BitXorAssign, BitAndAssign};
 // use deku::prelude::*;
 use bitvec::prelude::*;
 
+use crate::{raycast, RaycastHit};
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ShiftDirection {
     Front = 0,
     Back = 1,
@@ -25,9 +27,56 @@ impl ShiftDirection {
             _ => panic!("Invalid shift direction: {}", number),
         }
     }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::Front  => Self::Back,
+            Self::Back   => Self::Front,
+            Self::Left   => Self::Right,
+            Self::Right  => Self::Left,
+            Self::Top    => Self::Bottom,
+            Self::Bottom => Self::Top,
+        }
+    }
+
+    /// Unit offset pointing from a cell to its neighbor in this direction.
+    pub fn normal(&self) -> glam::IVec3 {
+        match self {
+            Self::Front  => glam::IVec3::Z,
+            Self::Back   => glam::IVec3::NEG_Z,
+            Self::Left   => glam::IVec3::NEG_X,
+            Self::Right  => glam::IVec3::X,
+            Self::Top    => glam::IVec3::Y,
+            Self::Bottom => glam::IVec3::NEG_Y,
+        }
+    }
+}
+
+/// Describes the blocks just outside of one side of an array,
+/// which decides whether the faces on that side are kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Border {
+    /// The neighbor is unknown or empty, the border faces are emitted.
+    Emit,
+    /// The neighbor is solid, the border faces are culled.
+    Cull,
+    /// The outermost layer of the neighboring array on the opposite side,
+    /// as returned by `Array3D::get_border`.
+    Slice(BitVec),
+}
+
+impl Border {
+    /// index: position in the border layer, see `Array3D::get_border`
+    pub fn is_solid(&self, index: usize) -> bool {
+        match self {
+            Self::Emit => false,
+            Self::Cull => true,
+            Self::Slice(slice) => slice[index],
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Array3D {
     data: BitVec,
     size: usize,
@@ -53,6 +102,47 @@ impl Array3D {
         x + y * self.size + z * self.size.pow(2)
     }
 
+    /// Inverse of `get_index`, returns the (x, y, z) position of the cell.
+    pub fn get_position(&self, index: usize) -> (usize, usize, usize) {
+        (index % self.size, index / self.size % self.size, index / self.size.pow(2))
+    }
+
+    /// Index of the cell at position (a, b) of the outermost layer on the given side.
+    /// Sides perpendicular to x are indexed by (y, z), to y by (x, z) and to z by (x, y).
+    fn get_border_index(&self, side: ShiftDirection, a: usize, b: usize) -> usize {
+        let last = self.size - 1;
+
+        match side {
+            ShiftDirection::Front  => self.get_index(a, b, last),
+            ShiftDirection::Back   => self.get_index(a, b, 0),
+            ShiftDirection::Left   => self.get_index(0, a, b),
+            ShiftDirection::Right  => self.get_index(last, a, b),
+            ShiftDirection::Top    => self.get_index(a, last, b),
+            ShiftDirection::Bottom => self.get_index(a, 0, b),
+        }
+    }
+
+    /// Returns the outermost layer on the given side, the cell (a, b) is at index a + b * size.
+    pub fn get_border(&self, side: ShiftDirection) -> BitVec {
+        let mut border = BitVec::with_capacity(self.size.pow(2));
+
+        for b in 0..self.size {
+            for a in 0..self.size {
+                border.push(self.data[self.get_border_index(side, a, b)]);
+            }
+        }
+
+        border
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn data(&self) -> &BitSlice {
+        self.data.as_bitslice()
+    }
+
     pub fn data_mut(&mut self) -> &mut BitSlice {
         self.data.as_mut_bitslice()
     }
@@ -66,7 +156,6 @@ impl Array3D {
         self.data[index]
     }
 
-    // todo: handle group_len 
     pub fn set(&mut self, x: usize, y: usize, z: usize, value: bool) {
         if x >= self.size || y >= self.size || z >= self.size {
             panic!("Index out of bounds: ({}, {}, {})", x, y, z);
@@ -77,9 +166,7 @@ impl Array3D {
     }
 
     fn copy_bitvec(&self) -> BitVec {
-        let mut copy = BitVec::new();
-        copy.copy_from_bitslice(self.data.as_bitslice());
-        copy
+        self.data.clone()
     }
 
 
@@ -127,22 +214,152 @@ impl Array3D {
         slice
     }
 
-    pub fn compare_shifted(&self, shift_direction: ShiftDirection) -> BitVec {
-        let mut shifted = self.get_shifted(shift_direction);
+    /// Casts a ray through the array, cells outside of it are treated as empty.
+    /// The cell (x, y, z) spans from (x, y, z) to (x + 1, y + 1, z + 1).
+    pub fn raycast(&self, origin: glam::Vec3, direction: glam::Vec3, max_distance: f32) -> Option<RaycastHit> {
+        let size = self.size as i32;
+
+        raycast(origin, direction, max_distance, |cell| {
+            cell.cmpge(glam::IVec3::ZERO).all()
+                && cell.cmplt(glam::IVec3::splat(size)).all()
+                && self.get(cell.x as usize, cell.y as usize, cell.z as usize)
+        })
+    }
+
+    /// Returns for every cell whether its neighbor in the given direction is set.
+    /// The flat shift wraps across rows and slices, so the outermost layer 
+    /// on that side is overwritten with the values given by the border.
+    pub fn get_neighbors(&self, shift_direction: ShiftDirection, border: &Border) -> BitVec {
+        let mut neighbors = self.get_shifted(shift_direction);
+
+        for b in 0..self.size {
+            for a in 0..self.size {
+                let index = self.get_border_index(shift_direction, a, b);
+                neighbors.set(index, border.is_solid(a + b * self.size));
+            }
+        }
+
+        neighbors
+    }
+
+    pub fn compare_shifted(&self, shift_direction: ShiftDirection, border: &Border) -> BitVec {
+        let mut shifted = self.get_neighbors(shift_direction, border);
         shifted.bitxor_assign(&self.data);
         shifted.bitand_assign(&self.data);
         shifted
     }
 
+    /// Returns the visible faces of each side, emitting all faces on the outer border.
     pub fn get_faces(&self) -> [BitVec; 6] {
+        self.get_faces_with_borders(&[Border::Emit, Border::Emit, Border::Emit, Border::Emit, Border::Emit, Border::Emit])
+    }
+
+    /// Returns the visible faces of each side, borders are ordered the same as `ShiftDirection`.
+    pub fn get_faces_with_borders(&self, borders: &[Border; 6]) -> [BitVec; 6] {
         [
-            self.compare_shifted(ShiftDirection::Front),
-            self.compare_shifted(ShiftDirection::Back),
-            self.compare_shifted(ShiftDirection::Left),
-            self.compare_shifted(ShiftDirection::Right),
-            self.compare_shifted(ShiftDirection::Top),
-            self.compare_shifted(ShiftDirection::Bottom),
+            self.compare_shifted(ShiftDirection::Front,  &borders[ShiftDirection::Front as usize]),
+            self.compare_shifted(ShiftDirection::Back,   &borders[ShiftDirection::Back as usize]),
+            self.compare_shifted(ShiftDirection::Left,   &borders[ShiftDirection::Left as usize]),
+            self.compare_shifted(ShiftDirection::Right,  &borders[ShiftDirection::Right as usize]),
+            self.compare_shifted(ShiftDirection::Top,    &borders[ShiftDirection::Top as usize]),
+            self.compare_shifted(ShiftDirection::Bottom, &borders[ShiftDirection::Bottom as usize]),
         ]
     }
+
+    /// Returns the faces of the set cells of each side that are not hidden by a set cell of the occluders,
+    /// so cells that can be seen through do not cull their neighbors. 
+    /// The borders describe the occluders behind each side.
+    pub fn get_faces_with_occluders(&self, occluders: &Array3D, borders: &[Border; 6]) -> [BitVec; 6] {
+        std::array::from_fn(|side| {
+            let side = ShiftDirection::from_number(side);
+
+            let mut faces = occluders.get_neighbors(side, &borders[side as usize]);
+            faces.bitxor_assign(&self.data);
+            faces.bitand_assign(&self.data);
+            faces
+        })
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 16;
+    const EMIT: [Border; 6] = [Border::Emit, Border::Emit, Border::Emit, Border::Emit, Border::Emit, Border::Emit];
+    const CULL: [Border; 6] = [Border::Cull, Border::Cull, Border::Cull, Border::Cull, Border::Cull, Border::Cull];
+
+    fn array(blocks: &[(usize, usize, usize)]) -> Array3D {
+        let mut array = Array3D::new(SIZE);
+        for &(x, y, z) in blocks {
+            array.set(x, y, z, true);
+        }
+        array
+    }
+
+    fn has_face(faces: &[BitVec; 6], side: ShiftDirection, (x, y, z): (usize, usize, usize)) -> bool {
+        faces[side as usize][x + y * SIZE + z * SIZE * SIZE]
+    }
+
+    /// The last cell of a row, slice or the cube is followed by the first one of the next in the flat data,
+    /// so a and b are neighbors in the data but not in space.
+    fn check_seam(side: ShiftDirection, a: (usize, usize, usize), b: (usize, usize, usize)) {
+        let faces = array(&[a, b]).get_faces();
+        assert!(has_face(&faces, side, a), "{:?} of {:?} is culled by {:?}", side, a, b);
+        assert!(has_face(&faces, side.opposite(), b), "{:?} of {:?} is culled by {:?}", side.opposite(), b, a);
+
+        // the empty cell across the seam does not make the side visible either
+        let faces = array(&[b]).get_faces_with_borders(&CULL);
+        assert!(!has_face(&faces, side.opposite(), b), "{:?} of {:?} is not culled by the border", side.opposite(), b);
+    }
+
+    #[test]
+    fn row_seam() {
+        check_seam(ShiftDirection::Right, (15, 3, 4), (0, 4, 4));
+    }
+
+    #[test]
+    fn slice_seam() {
+        check_seam(ShiftDirection::Top, (3, 15, 4), (3, 0, 5));
+    }
+
+    #[test]
+    fn cube_seam() {
+        let faces = array(&[(3, 4, 15), (3, 4, 0)]).get_faces_with_borders(&CULL);
+        assert!(!has_face(&faces, ShiftDirection::Front, (3, 4, 15)));
+        assert!(!has_face(&faces, ShiftDirection::Back, (3, 4, 0)));
+    }
+
+    #[test]
+    fn emit_border_keeps_the_outer_faces() {
+        let faces = array(&[(0, 0, 0), (15, 15, 15)]).get_faces_with_borders(&EMIT);
+
+        for side in 0..6 {
+            let side = ShiftDirection::from_number(side);
+            assert!(has_face(&faces, side, (0, 0, 0)), "{:?} of the first cell", side);
+            assert!(has_face(&faces, side, (15, 15, 15)), "{:?} of the last cell", side);
+        }
+    }
+
+    #[test]
+    fn cull_border_hides_only_the_outer_faces() {
+        let faces = array(&[(0, 5, 5)]).get_faces_with_borders(&CULL);
+
+        assert!(!has_face(&faces, ShiftDirection::Left, (0, 5, 5)));
+        for side in [ShiftDirection::Right, ShiftDirection::Top, ShiftDirection::Bottom, ShiftDirection::Front, ShiftDirection::Back] {
+            assert!(has_face(&faces, side, (0, 5, 5)), "{:?} is culled", side);
+        }
+    }
+
+    #[test]
+    fn slice_border_culls_against_the_neighbor() {
+        let neighbor = array(&[(0, 2, 3)]);
+        let mut borders = EMIT;
+        borders[ShiftDirection::Right as usize] = Border::Slice(neighbor.get_border(ShiftDirection::Left));
+
+        let faces = array(&[(15, 2, 3), (15, 5, 6)]).get_faces_with_borders(&borders);
+        assert!(!has_face(&faces, ShiftDirection::Right, (15, 2, 3)));
+        assert!(has_face(&faces, ShiftDirection::Right, (15, 5, 6)));
+    }
+}
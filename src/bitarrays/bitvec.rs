@@ -1,60 +1,238 @@
-use crate::Axis;
-
-/// Treats the inside data as an array of singular bits or groups of them 
+/// Treats the inside data as an array of singular bits or groups of them
 /// with a group size given by group_len (defaults to 1 bit).
-pub struct BitVec {
+/// Groups are stored from the most significant bit of each chunk and never span two chunks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupedBitVec {
     data: Vec<u64>,
     group_len: usize,
     len: usize,
     basic_mask: u64,
 }
 
-impl BitVec {
+impl GroupedBitVec {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn group_len(&self) -> usize {
+        self.group_len
+    }
+
+    /// The raw chunks holding the groups.
+    pub fn data(&self) -> &[u64] {
+        &self.data
+    }
+}
+
+impl GroupedBitVec {
     const CHUNK_LEN: usize = 64;
 
     /// get the mask for a given size (in bits), starting from the left
     fn create_mask(size: usize, start: usize) -> u64 {
-        !(std::u64::MAX >> (size)) >> start
+        !(u64::MAX.checked_shr(size as u32).unwrap_or(0)) >> start
+    }
+
+    fn groups_per_chunk(group_len: usize) -> usize {
+        Self::CHUNK_LEN / group_len
+    }
+
+    /// Number of chunks holding `len` groups of `group_len` bits.
+    pub fn chunk_count(group_len: usize, len: usize) -> usize {
+        len.div_ceil(Self::groups_per_chunk(group_len))
+    }
+
+    /// Returns the chunk index and the bit offset from the left of the group.
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let groups_per_chunk = Self::groups_per_chunk(self.group_len);
+        let chunk_index = index / groups_per_chunk;
+        let group_start = index % groups_per_chunk * self.group_len;
+
+        (chunk_index, group_start)
     }
 
+    /// args:
+    ///  - group_len: bits per group, between 1 and 64
     pub fn new(group_len: usize) -> Self {
+        Self::with_len(group_len, 0)
+    }
+
+    /// Creates a vector of `len` groups, all set to zero.
+    pub fn with_len(group_len: usize, len: usize) -> Self {
+        if group_len == 0 || group_len > Self::CHUNK_LEN {
+            panic!("Invalid group length: {}", group_len);
+        }
+
         Self {
-            data: Vec::new(),
+            data: vec![0; Self::chunk_count(group_len, len)],
             group_len,
-            len: 0,
-            basic_mask: Self::create_mask(1, 0),
+            len,
+            basic_mask: Self::create_mask(group_len, 0),
         }
     }
 
-    pub fn get_group(&self, index: usize, base: usize) -> u64 {
-        let chunk_index = index / Self::CHUNK_LEN;
-        let group_start = index % Self::CHUNK_LEN;
+    /// Rebuilds a vector from the chunks returned by `data`.
+    pub fn from_raw(group_len: usize, len: usize, data: Vec<u64>) -> Option<Self> {
+        let mut bitvec = Self::with_len(group_len, 0);
+
+        if data.len() != Self::chunk_count(group_len, len) {
+            return None;
+        }
+
+        bitvec.data = data;
+        bitvec.len = len;
+        Some(bitvec)
+    }
+
+    /// The largest value a single group can hold.
+    pub fn max_value(&self) -> u64 {
+        self.basic_mask >> (Self::CHUNK_LEN - self.group_len)
+    }
+
+    pub fn get_group(&self, index: usize) -> u64 {
+        if index >= self.len {
+            panic!("Index out of bounds: {} (len {})", index, self.len);
+        }
+
+        let (chunk_index, group_start) = self.locate(index);
 
         let chunk = self.data[chunk_index];
         let mask = self.basic_mask >> group_start;
 
-        (chunk & mask) << group_start >> base
+        (chunk & mask) >> (Self::CHUNK_LEN - group_start - self.group_len)
     }
 
     pub fn set_group(&mut self, index: usize, value: u64) {
-        let chunk_index = index / Self::CHUNK_LEN;
-        let group_start = index % Self::CHUNK_LEN;
+        if index >= self.len {
+            panic!("Index out of bounds: {} (len {})", index, self.len);
+        }
+
+        if value > self.max_value() {
+            panic!("Value {} does not fit into {} bits", value, self.group_len);
+        }
+
+        let (chunk_index, group_start) = self.locate(index);
 
         let chunk = self.data[chunk_index];
         let mask = self.basic_mask >> group_start;
 
-        let value = value << (64 - 1 - group_start);
+        let value = value << (Self::CHUNK_LEN - group_start - self.group_len);
 
         self.data[chunk_index] = (chunk & !mask) | value;
     }
 
-    pub fn get_multiple_groups(&self, start: usize, count: usize) -> u64 {
-        let mut result = 0;
+    pub fn push(&mut self, value: u64) {
+        self.resize(self.len + 1);
+        self.set_group(self.len - 1, value);
+    }
+
+    /// Changes the number of groups, new groups are set to zero.
+    pub fn resize(&mut self, len: usize) {
+        if len < self.len {
+            // clear the removed groups, so they are zero when grown back
+            for index in len..self.len {
+                self.set_group(index, 0);
+            }
+        }
+
+        self.data.resize(Self::chunk_count(self.group_len, len), 0);
+        self.len = len;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        (0..self.len).map(|index| self.get_group(index))
+    }
+
+    /// Copies every group into a new vector with a different group length.
+    /// Panics if a value does not fit into the new group length.
+    pub fn with_group_len(&self, group_len: usize) -> Self {
+        let mut bitvec = Self::with_len(group_len, self.len);
+
+        for (index, value) in self.iter().enumerate() {
+            bitvec.set_group(index, value);
+        }
+
+        bitvec
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills every group with a different value, wrapped to the group length.
+    fn pattern(bitvec: &GroupedBitVec, index: usize) -> u64 {
+        (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) & bitvec.max_value()
+    }
 
-        for i in 0..count {
-            result |= self.get_group(start + i, self.group_len * i);
+    #[test]
+    fn groups_around_chunk_boundaries() {
+        for group_len in [1, 3, 5, 7, 13, 32, 63, 64] {
+            let mut bitvec = GroupedBitVec::with_len(group_len, 200);
+
+            for index in 0..bitvec.len() {
+                bitvec.set_group(index, pattern(&bitvec, index));
+            }
+            for index in 0..bitvec.len() {
+                assert_eq!(bitvec.get_group(index), pattern(&bitvec, index), "group {} of {} bits", index, group_len);
+            }
         }
+    }
+
+    #[test]
+    fn set_group_keeps_the_neighbors() {
+        // 12 groups of 5 bits fit into a chunk, the last 4 bits are unused
+        let mut bitvec = GroupedBitVec::with_len(5, 30);
+        for index in [10, 11, 12, 13] {
+            bitvec.set_group(index, 31);
+        }
+
+        bitvec.set_group(11, 0);
+        bitvec.set_group(12, 17);
+
+        assert_eq!(bitvec.data().len(), 3);
+        assert_eq!(bitvec.iter().skip(9).take(6).collect::<Vec<_>>(), [0, 31, 0, 17, 31, 0]);
+        assert_eq!(bitvec.data()[0] & 0xF, 0, "the unused bits of the first chunk stay zero");
+    }
+
+    #[test]
+    fn full_width_groups() {
+        let mut bitvec = GroupedBitVec::with_len(64, 3);
+        bitvec.set_group(1, u64::MAX);
+
+        assert_eq!(bitvec.max_value(), u64::MAX);
+        assert_eq!(bitvec.iter().collect::<Vec<_>>(), [0, u64::MAX, 0]);
+    }
+
+    #[test]
+    fn resize_clears_the_removed_groups() {
+        let mut bitvec = GroupedBitVec::with_len(6, 12);
+        bitvec.set_group(11, 63);
+        bitvec.resize(11);
+        bitvec.push(0);
+
+        assert_eq!(bitvec.get_group(11), 0);
+    }
+
+    #[test]
+    fn with_group_len_keeps_the_values() {
+        let mut bitvec = GroupedBitVec::with_len(3, 40);
+        for index in 0..bitvec.len() {
+            bitvec.set_group(index, pattern(&bitvec, index));
+        }
+
+        let wider = bitvec.with_group_len(9);
+        assert_eq!(wider.iter().collect::<Vec<_>>(), bitvec.iter().collect::<Vec<_>>());
+        assert_eq!(GroupedBitVec::from_raw(3, 40, bitvec.data().to_vec()), Some(bitvec));
+    }
 
-        result
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn set_group_rejects_wide_values() {
+        GroupedBitVec::with_len(4, 1).set_group(0, 16);
     }
 }
@@ -0,0 +1,84 @@
+use crate::{Array3D, GroupedBitVec};
+
+/// 3D array of block ids, each stored in a fixed number of bits.
+/// The id 0 is air, everything else is solid.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockArray3D {
+    data: GroupedBitVec,
+    size: usize,
+}
+
+impl BlockArray3D {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn bits_per_block(&self) -> usize {
+        self.data.group_len()
+    }
+
+    pub fn data(&self) -> &GroupedBitVec {
+        &self.data
+    }
+}
+
+impl BlockArray3D {
+    pub const AIR: u64 = 0;
+
+    /// Creates a new 3D array filled with air.
+    /// Size is the length of one side of the array cube.
+    pub fn new(size: usize, bits_per_block: usize) -> Self {
+        Self {
+            data: GroupedBitVec::with_len(bits_per_block, size.pow(3)),
+            size,
+        }
+    }
+
+    /// Wraps already filled data, returns None if its length does not match the size.
+    pub fn from_data(size: usize, data: GroupedBitVec) -> Option<Self> {
+        if data.len() != size.pow(3) {
+            return None;
+        }
+
+        Some(Self { data, size })
+    }
+
+    fn get_index(&self, x: usize, y: usize, z: usize) -> usize {
+        if x >= self.size || y >= self.size || z >= self.size {
+            panic!("Index out of bounds: ({}, {}, {})", x, y, z);
+        }
+
+        x + y * self.size + z * self.size.pow(2)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> u64 {
+        self.data.get_group(self.get_index(x, y, z))
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: u64) {
+        let index = self.get_index(x, y, z);
+        self.data.set_group(index, block);
+    }
+
+    /// Returns a copy of the array using a different number of bits per block.
+    pub fn with_bits_per_block(&self, bits_per_block: usize) -> Self {
+        Self {
+            data: self.data.with_group_len(bits_per_block),
+            size: self.size,
+        }
+    }
+
+    /// Derives the 1-bit mask of non-air blocks, used for face extraction.
+    pub fn solid_mask(&self) -> Array3D {
+        let mut mask = Array3D::new(self.size);
+        let bits = mask.data_mut();
+
+        for (index, block) in self.data.iter().enumerate() {
+            if block != Self::AIR {
+                bits.set(index, true);
+            }
+        }
+
+        mask
+    }
+}
@@ -1,8 +1,14 @@
-// pub mod bitvec;
-// pub use bitvec::*;
+pub mod bitvec;
+pub use self::bitvec::*;
 
 pub mod array3d;
 pub use array3d::*;
 
+pub mod block_array3d;
+pub use block_array3d::*;
+
+pub mod paletted_array3d;
+pub use paletted_array3d::*;
+
 // pub mod axis;
 // pub use axis::*;
@@ -0,0 +1,303 @@
+use crate::{Array3D, BlockArray3D};
+
+/// Global id of a block type, shared by all chunks.
+pub type BlockId = u16;
+
+pub const AIR: BlockId = 0;
+
+/// 3D array of block ids stored as indices into a local palette.
+/// The bits per block grow and shrink with the number of distinct blocks,
+/// so an array of a single block type takes 1 bit per block.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PalettedArray3D {
+    palette: Vec<BlockId>,
+    counts: Vec<usize>, // number of blocks using each palette entry, unused entries are 0
+    indices: BlockArray3D,
+}
+
+impl PalettedArray3D {
+    pub fn size(&self) -> usize {
+        self.indices.size()
+    }
+
+    pub fn bits_per_block(&self) -> usize {
+        self.indices.bits_per_block()
+    }
+
+    /// The palette entries, some of them may be unused.
+    pub fn palette(&self) -> &[BlockId] {
+        &self.palette
+    }
+
+    pub fn indices(&self) -> &BlockArray3D {
+        &self.indices
+    }
+
+    /// Number of distinct blocks in the array.
+    pub fn block_count(&self) -> usize {
+        self.counts.iter().filter(|count| **count > 0).count()
+    }
+}
+
+impl PalettedArray3D {
+    /// Creates a new array filled with air.
+    pub fn new(size: usize) -> Self {
+        Self::filled(size, AIR)
+    }
+
+    pub fn filled(size: usize, block: BlockId) -> Self {
+        Self {
+            palette: vec![block],
+            counts: vec![size.pow(3)],
+            indices: BlockArray3D::new(size, 1),
+        }
+    }
+
+    /// Rebuilds an array from its palette and indices, returns None if an index is outside of the palette.
+    pub fn from_parts(palette: Vec<BlockId>, indices: BlockArray3D) -> Option<Self> {
+        let mut counts = vec![0; palette.len()];
+
+        for index in indices.data().iter() {
+            *counts.get_mut(index as usize)? += 1;
+        }
+
+        let mut array = Self {
+            palette,
+            counts,
+            indices,
+        };
+        array.compact();
+
+        Some(array)
+    }
+
+    /// Bits needed to index a palette of the given length.
+    fn bits_for(palette_len: usize) -> usize {
+        let bits = usize::BITS - palette_len.saturating_sub(1).leading_zeros();
+        (bits as usize).max(1)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockId {
+        self.palette[self.indices.get(x, y, z) as usize]
+    }
+
+    /// Sets the block and returns the previous one.
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: BlockId) -> BlockId {
+        let old_index = self.indices.get(x, y, z) as usize;
+        let old_block = self.palette[old_index];
+
+        if old_block == block {
+            return old_block;
+        }
+
+        let new_index = self.get_or_insert(block);
+        self.indices.set(x, y, z, new_index as u64);
+        self.counts[new_index] += 1;
+        self.counts[old_index] -= 1;
+
+        // hysteresis: repack only once the blocks fit into two bits less, so toggling a block
+        // back and forth at a bit width boundary does not repack every time.
+        // A palette that fits into one bit less keeps the extra bit until then or until `compact`
+        let bits = self.bits_per_block();
+        if self.counts[old_index] == 0 && bits > 1 && self.block_count() <= 1 << (bits - 2) {
+            self.compact();
+        }
+
+        old_block
+    }
+
+    /// Returns the palette index of the block, adding it to the palette if needed.
+    fn get_or_insert(&mut self, block: BlockId) -> usize {
+        if let Some(index) = self.palette.iter().position(|entry| *entry == block) {
+            return index;
+        }
+
+        // reuse entries that are no longer used
+        if let Some(index) = self.counts.iter().position(|count| *count == 0) {
+            self.palette[index] = block;
+            return index;
+        }
+
+        self.palette.push(block);
+        self.counts.push(0);
+
+        let bits = Self::bits_for(self.palette.len());
+        if bits > self.bits_per_block() {
+            self.indices = self.indices.with_bits_per_block(bits);
+        }
+
+        self.palette.len() - 1
+    }
+
+    /// Drops the unused palette entries and repacks the indices with the fewest bits possible.
+    pub fn compact(&mut self) {
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        let mut counts = Vec::new();
+
+        for (index, (block, count)) in self.palette.iter().zip(self.counts.iter()).enumerate() {
+            if *count > 0 {
+                remap[index] = palette.len() as u64;
+                palette.push(*block);
+                counts.push(*count);
+            }
+        }
+
+        let size = self.size();
+        let mut indices = BlockArray3D::new(size, Self::bits_for(palette.len()));
+
+        for z in 0..size {
+            for y in 0..size {
+                for x in 0..size {
+                    indices.set(x, y, z, remap[self.indices.get(x, y, z) as usize]);
+                }
+            }
+        }
+
+        self.palette = palette;
+        self.counts = counts;
+        self.indices = indices;
+    }
+
+    /// Derives the 1-bit mask of non-air blocks, used for face extraction.
+    pub fn solid_mask(&self) -> Array3D {
+        self.mask(|block| block != AIR)
+    }
+
+    /// Derives the 1-bit mask of the blocks for which the predicate returns true,
+    /// the predicate is called once per palette entry.
+    pub fn mask(&self, predicate: impl Fn(BlockId) -> bool) -> Array3D {
+        let included: Vec<bool> = self.palette.iter().map(|block| predicate(*block)).collect();
+
+        let mut mask = Array3D::new(self.size());
+        let bits = mask.data_mut();
+
+        for (index, entry) in self.indices.data().iter().enumerate() {
+            if included[entry as usize] {
+                bits.set(index, true);
+            }
+        }
+
+        mask
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fills the first `count` blocks along x with the ids 1 to `count`.
+    fn with_blocks(count: usize) -> PalettedArray3D {
+        let mut array = PalettedArray3D::new(16);
+        for index in 0..count {
+            array.set(index, 0, 0, index as BlockId + 1);
+        }
+
+        array
+    }
+
+    #[test]
+    fn set_returns_the_previous_block() {
+        let mut array = PalettedArray3D::new(4);
+
+        assert_eq!(array.set(1, 2, 3, 5), AIR);
+        assert_eq!(array.set(1, 2, 3, 7), 5);
+        assert_eq!(array.set(1, 2, 3, 7), 7);
+
+        assert_eq!(array.get(1, 2, 3), 7);
+        assert_eq!(array.get(0, 0, 0), AIR);
+        assert_eq!(array.block_count(), 2);
+    }
+
+    #[test]
+    fn bits_grow_with_the_palette() {
+        let array = with_blocks(4);
+
+        // air and 4 blocks
+        assert_eq!(array.block_count(), 5);
+        assert_eq!(array.bits_per_block(), 3);
+    }
+
+    #[test]
+    fn unused_palette_entries_are_reused() {
+        let mut array = with_blocks(3);
+        let palette_len = array.palette().len();
+
+        array.set(0, 0, 0, AIR);
+        array.set(1, 0, 0, 9);
+
+        assert_eq!(array.palette().len(), palette_len);
+        assert_eq!(array.palette().iter().filter(|block| **block == 9).count(), 1);
+        assert_eq!(array.get(1, 0, 0), 9);
+    }
+
+    #[test]
+    fn compacts_once_a_whole_bit_is_free() {
+        // air and 4 blocks take 3 bits
+        let mut array = with_blocks(4);
+
+        // 3 blocks still need 2 bits, nothing is repacked yet
+        array.set(3, 0, 0, AIR);
+        array.set(2, 0, 0, AIR);
+        assert_eq!(array.bits_per_block(), 3);
+
+        // 2 blocks fit into 1 bit
+        array.set(1, 0, 0, AIR);
+        assert_eq!(array.bits_per_block(), 1);
+        assert_eq!(array.palette(), &[AIR, 1]);
+        assert_eq!(array.get(0, 0, 0), 1);
+        assert_eq!(array.get(1, 0, 0), AIR);
+    }
+
+    #[test]
+    fn keeps_the_extra_bit_until_compacted() {
+        // air and 4 blocks take 3 bits
+        let mut array = with_blocks(4);
+
+        // 4 blocks fit into 2 bits, but only a quarter of the 3 bit palette frees a bit
+        array.set(3, 0, 0, AIR);
+        for _ in 0..4 {
+            array.set(5, 0, 0, 3);
+            array.set(5, 0, 0, AIR);
+        }
+        assert_eq!(array.block_count(), 4);
+        assert_eq!(array.bits_per_block(), 3);
+
+        array.compact();
+        assert_eq!(array.bits_per_block(), 2);
+    }
+
+    #[test]
+    fn toggling_at_a_boundary_does_not_repack() {
+        // air and 2 blocks take 2 bits
+        let mut array = with_blocks(2);
+        assert_eq!(array.bits_per_block(), 2);
+
+        for _ in 0..4 {
+            array.set(1, 0, 0, AIR);
+            assert_eq!(array.bits_per_block(), 2);
+            array.set(1, 0, 0, 2);
+            assert_eq!(array.bits_per_block(), 2);
+        }
+
+        assert_eq!(array.palette().len(), 3);
+    }
+
+    #[test]
+    fn compact_keeps_the_blocks() {
+        let mut array = with_blocks(5);
+        for x in 0..3 {
+            array.set(x, 0, 0, AIR);
+        }
+
+        let before = array.clone();
+        array.compact();
+
+        assert_eq!(array.palette(), &[AIR, 4, 5]);
+        assert_eq!(array.bits_per_block(), 2);
+        for x in 0..16 {
+            assert_eq!(array.get(x, 0, 0), before.get(x, 0, 0));
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::path::Path;
+
+use crate::{BlockId, ShiftDirection, Aabb};
+
+
+/// Names of the textures on the faces of a block, without the directory and extension.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaceTextures {
+    pub top: String,
+    /// Front, back, left and right
+    pub side: String,
+    pub bottom: String,
+}
+
+impl FaceTextures {
+    /// The same texture on every face.
+    pub fn all(name: &str) -> Self {
+        Self {
+            top: name.to_string(),
+            side: name.to_string(),
+            bottom: name.to_string(),
+        }
+    }
+
+    pub fn new(top: &str, side: &str, bottom: &str) -> Self {
+        Self {
+            top: top.to_string(),
+            side: side.to_string(),
+            bottom: bottom.to_string(),
+        }
+    }
+
+    pub fn get(&self, face: ShiftDirection) -> &str {
+        match face {
+            ShiftDirection::Top    => &self.top,
+            ShiftDirection::Bottom => &self.bottom,
+            _ => &self.side,
+        }
+    }
+}
+
+
+/// The part of a block the player collides with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollisionShape {
+    None,
+    Full,
+    /// Box within the block, from (0, 0, 0) to at most (1, 1, 1)
+    Box(Aabb),
+}
+
+impl CollisionShape {
+    /// The shape in world coordinates for the block at the given position.
+    pub fn aabb(&self, block: glam::IVec3) -> Option<Aabb> {
+        match self {
+            Self::None => None,
+            Self::Full => Some(Aabb::new(glam::Vec3::ZERO, glam::Vec3::ONE).translated(block.as_vec3())),
+            Self::Box(aabb) => Some(aabb.translated(block.as_vec3())),
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockDefinition {
+    pub name: String,
+    /// Collides with the player through its collision shape and stops raycasts
+    pub solid: bool,
+    /// Hides the faces of the neighboring blocks, false for blocks that can be seen through
+    pub opaque: bool,
+    /// None for blocks that are never drawn, like air
+    pub textures: Option<FaceTextures>,
+    pub collision: CollisionShape,
+}
+
+impl BlockDefinition {
+    /// A solid and opaque block with the same texture as its name on every face.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            solid: true,
+            opaque: true,
+            textures: Some(FaceTextures::all(name)),
+            collision: CollisionShape::Full,
+        }
+    }
+
+    pub fn air() -> Self {
+        Self {
+            name: "air".to_string(),
+            solid: false,
+            opaque: false,
+            textures: None,
+            collision: CollisionShape::None,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(std::io::Error),
+    /// Line number, starting at 1, and what is wrong with it
+    Parse(usize, String),
+}
+
+impl std::fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Block registry io error: {}", error),
+            Self::Parse(line, message) => write!(f, "Block registry error on line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<std::io::Error> for RegistryError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+
+/// Definitions of the blocks, indexed by their id.
+/// Ids missing in the registry are treated as air.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockDefinition>,
+}
+
+impl BlockRegistry {
+    pub fn get(&self, id: BlockId) -> Option<&BlockDefinition> {
+        self.blocks.get(id as usize)
+    }
+
+    pub fn find(&self, name: &str) -> Option<BlockId> {
+        self.blocks.iter().position(|block| block.name == name).map(|id| id as BlockId)
+    }
+
+    pub fn blocks(&self) -> &[BlockDefinition] {
+        &self.blocks
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn is_solid(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|block| block.solid)
+    }
+
+    pub fn is_opaque(&self, id: BlockId) -> bool {
+        self.get(id).is_some_and(|block| block.opaque)
+    }
+
+    /// The collision box of the block at the given world position, None if the block is not solid.
+    pub fn collision_box(&self, id: BlockId, block: glam::IVec3) -> Option<Aabb> {
+        self.get(id)
+            .filter(|definition| definition.solid)
+            .and_then(|definition| definition.collision.aabb(block))
+    }
+}
+
+impl BlockRegistry {
+    /// The file the default registry is built from
+    pub const PATH: &'static str = "res/blocks.cfg";
+
+    /// Only contains air, with the id `AIR`.
+    pub fn new() -> Self {
+        Self {
+            blocks: vec![BlockDefinition::air()],
+        }
+    }
+
+    /// Returns the id of the new block.
+    pub fn register(&mut self, block: BlockDefinition) -> BlockId {
+        self.blocks.push(block);
+        (self.blocks.len() - 1) as BlockId
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Every `[name]` line starts a new block, followed by its `property = value` lines,
+    /// see res/blocks.cfg for the properties. Lines starting with # are comments.
+    pub fn parse(text: &str) -> Result<Self, RegistryError> {
+        let mut registry = Self::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| RegistryError::Parse(index + 1, message);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let name = name.trim();
+
+                if registry.find(name).is_some() {
+                    return Err(error(format!("block `{}` is defined twice", name)));
+                }
+
+                registry.register(BlockDefinition::new(name));
+                continue;
+            }
+
+            let (property, value) = line.split_once('=')
+                .ok_or_else(|| error(format!("expected `property = value` or `[block]`, found `{}`", line)))?;
+            let (property, value) = (property.trim(), value.trim());
+
+            if registry.blocks.len() == 1 {
+                return Err(error("property before the first block".to_string()));
+            }
+            let block = registry.blocks.last_mut().unwrap();
+
+            let parse_bool = |value: &str| match value {
+                "true"  => Ok(true),
+                "false" => Ok(false),
+                _ => Err(error(format!("expected true or false, found `{}`", value))),
+            };
+
+            match property {
+                "solid"  => block.solid = parse_bool(value)?,
+                "opaque" => block.opaque = parse_bool(value)?,
+                "textures" => {
+                    let names: Vec<&str> = value.split(',').map(str::trim).collect();
+
+                    block.textures = match names[..] {
+                        ["none"] => None,
+                        [name] => Some(FaceTextures::all(name)),
+                        [top, side, bottom] => Some(FaceTextures::new(top, side, bottom)),
+                        _ => return Err(error(format!("expected one or three textures, found `{}`", value))),
+                    };
+                },
+                "collision" => block.collision = parse_collision(value)
+                    .ok_or_else(|| error(format!("expected full, none or box with 6 numbers, found `{}`", value)))?,
+                _ => return Err(error(format!("unknown property `{}`", property))),
+            }
+        }
+
+        Ok(registry)
+    }
+
+    /// Every texture used by the blocks, each listed once in the order they first appear.
+    pub fn texture_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+
+        for textures in self.blocks.iter().filter_map(|block| block.textures.as_ref()) {
+            for name in [&textures.top, &textures.side, &textures.bottom] {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names
+    }
+
+    /// Index into `texture_names` of every face of every block, indexed by id * 6 + face.
+    /// Blocks without textures use layer 0.
+    pub fn face_layers(&self) -> Vec<u32> {
+        let names = self.texture_names();
+        let mut layers = Vec::with_capacity(self.blocks.len() * 6);
+
+        for block in self.blocks.iter() {
+            for face in 0..6 {
+                let layer = block.textures.as_ref()
+                    .and_then(|textures| names.iter().position(|name| *name == textures.get(ShiftDirection::from_number(face))))
+                    .unwrap_or(0);
+
+                layers.push(layer as u32);
+            }
+        }
+
+        layers
+    }
+}
+
+impl Default for BlockRegistry {
+    /// The blocks of res/blocks.cfg as it was at compile time.
+    fn default() -> Self {
+        Self::parse(include_str!("../res/blocks.cfg")).expect("res/blocks.cfg is invalid")
+    }
+}
+
+
+fn parse_collision(value: &str) -> Option<CollisionShape> {
+    let mut words = value.split_whitespace();
+
+    let shape = match words.next()? {
+        "none" => CollisionShape::None,
+        "full" => CollisionShape::Full,
+        "box" => {
+            let numbers: Vec<f32> = words.by_ref().map(|word| word.parse().ok()).collect::<Option<_>>()?;
+            if numbers.len() != 6 {
+                return None;
+            }
+
+            let min = glam::vec3(numbers[0], numbers[1], numbers[2]);
+            let max = glam::vec3(numbers[3], numbers[4], numbers[5]);
+            if min.cmplt(glam::Vec3::ZERO).any() || max.cmpgt(glam::Vec3::ONE).any() || min.cmpge(max).any() {
+                return None;
+            }
+
+            CollisionShape::Box(Aabb::new(min, max))
+        },
+        _ => return None,
+    };
+
+    match words.next() {
+        Some(_) => None,
+        None => Some(shape),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AIR;
+
+    fn error_line(text: &str) -> usize {
+        match BlockRegistry::parse(text) {
+            Err(RegistryError::Parse(line, _)) => line,
+            Err(error) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("{:?} was accepted", text),
+        }
+    }
+
+    #[test]
+    fn shipped_registry() {
+        let registry = BlockRegistry::load(BlockRegistry::PATH).unwrap();
+
+        assert_eq!(registry, BlockRegistry::default());
+        assert_eq!(registry.get(AIR), Some(&BlockDefinition::air()));
+        assert_eq!(registry.find("stone"), Some(1));
+        assert!(registry.is_opaque(registry.find("grass").unwrap()));
+        assert!(!registry.is_opaque(registry.find("glass").unwrap()));
+    }
+
+    #[test]
+    fn properties_and_defaults() {
+        let registry = BlockRegistry::parse("
+            # comment
+
+            [stone]
+            [grass]
+            textures = grass_top, grass_side, dirt
+            [flower]
+            solid = false
+            opaque = false
+            textures = flower
+            collision = none
+            [slab]
+            collision = box 0 0 0 1 0.5 1
+            [barrier]
+            textures = none
+        ").unwrap();
+
+        assert_eq!(registry.len(), 6);
+        assert_eq!(registry.get(1), Some(&BlockDefinition::new("stone")));
+        assert_eq!(registry.get(2).unwrap().textures, Some(FaceTextures::new("grass_top", "grass_side", "dirt")));
+
+        let flower = registry.get(3).unwrap();
+        assert!(!flower.solid && !flower.opaque);
+        assert_eq!(flower.collision, CollisionShape::None);
+
+        let slab = registry.collision_box(4, glam::ivec3(2, 3, 4)).unwrap();
+        assert_eq!(slab, Aabb::new(glam::vec3(2.0, 3.0, 4.0), glam::vec3(3.0, 3.5, 5.0)));
+        assert_eq!(registry.get(5).unwrap().textures, None);
+        assert_eq!(registry.texture_names(), ["stone", "grass_top", "grass_side", "dirt", "flower", "slab"]);
+    }
+
+    #[test]
+    fn ids_beyond_the_registry_are_air() {
+        let registry = BlockRegistry::default();
+        let missing = registry.len() as BlockId;
+
+        assert!(!registry.is_solid(missing));
+        assert_eq!(registry.collision_box(missing, glam::IVec3::ZERO), None);
+    }
+
+    #[test]
+    fn invalid_lines_are_errors() {
+        assert_eq!(error_line("solid = false"), 1);
+        assert_eq!(error_line("[stone]\n[stone]"), 2);
+        assert_eq!(error_line("[stone]\nsolid"), 2);
+        assert_eq!(error_line("[stone]\nsolid = maybe"), 2);
+        assert_eq!(error_line("[lamp]\n\nlight = 15"), 3);
+        assert_eq!(error_line("[stone]\ntextures = a, b"), 2);
+        assert_eq!(error_line("[slab]\ncollision = box 0 0 0 1 1"), 2);
+        assert_eq!(error_line("[slab]\ncollision = box 0 0 0 1 2 1"), 2);
+        assert_eq!(error_line("[slab]\ncollision = box 0 1 0 1 0.5 1"), 2);
+    }
+}
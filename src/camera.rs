@@ -1,6 +1,6 @@
 use wgpu::util::DeviceExt;
 
-use crate::HardwareState;
+use crate::{HardwareState, World, RaycastHit, Aabb, Frustum, InputMap, Action};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -20,10 +20,36 @@ impl CameraUniform {
     }
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementMode {
+    /// Free flight, passing through blocks
+    Flying,
+    /// Gravity, jumping and collisions with solid blocks
+    Walking,
+}
+
+impl MovementMode {
+    pub fn toggle(&mut self) {
+        match self {
+            Self::Flying  => *self = Self::Walking,
+            Self::Walking => *self = Self::Flying,
+        }
+    }
+}
 
 
 pub struct CameraController {
-    pressed_keys: [bool; 6],
+    movement_mode: MovementMode,
+    velocity: glam::Vec3, // only used while walking
+    on_ground: bool,
     translation: glam::Vec3,
     speed: f32,
     forward: glam::Vec3,
@@ -34,6 +60,14 @@ pub struct CameraController {
 }
 
 impl CameraController {
+    const PLAYER_HALF_WIDTH: f32 = 0.3;
+    const PLAYER_HEIGHT: f32 = 1.8;
+    /// Height of the camera above the player's feet
+    const EYE_HEIGHT: f32 = 1.6;
+    const GRAVITY: f32 = 25.0;
+    const JUMP_SPEED: f32 = 8.0;
+    const MAX_FALL_SPEED: f32 = 50.0;
+
     /// args: 
     ///  - mouse limit: the maximum angle the camera can look up or down, 0 to 1.0
     pub fn new(translation: glam::Vec3, sensitivity: f32, speed: f32, mouse_limit: Option<f32>) -> Self {
@@ -46,7 +80,9 @@ impl CameraController {
         }
 
         Self {
-            pressed_keys: [false; 6],
+            movement_mode: MovementMode::Flying,
+            velocity: glam::Vec3::ZERO,
+            on_ground: false,
             translation,
             speed,
             forward: glam::Vec3::NEG_Z,
@@ -57,67 +93,49 @@ impl CameraController {
         }
     }
 
-    pub fn handle_keyboard_input(&mut self, event: &winit::event::WindowEvent) -> bool {
-        match event {
-            winit::event::WindowEvent::KeyboardInput { 
-                input,
-                ..
-            } => {
-                let is_pressed = input.state == winit::event::ElementState::Pressed;
-
-                match input.virtual_keycode {
-                    Some(winit::event::VirtualKeyCode::W) => {
-                        self.pressed_keys[0] = is_pressed;
-                        true
-                    },
-                    Some(winit::event::VirtualKeyCode::S) => {
-                        self.pressed_keys[1] = is_pressed;
-                        true
-                    },
-                    Some(winit::event::VirtualKeyCode::A) => {
-                        self.pressed_keys[2] = is_pressed;
-                        true
-                    },
-                    Some(winit::event::VirtualKeyCode::D) => {
-                        self.pressed_keys[3] = is_pressed;
-                        true
-                    },
-                    Some(winit::event::VirtualKeyCode::Space) => {
-                        self.pressed_keys[4] = is_pressed;
-                        true
-                    },
-                    Some(winit::event::VirtualKeyCode::LControl) => {
-                        self.pressed_keys[5] = is_pressed;
-                        true
-                    },
-                    _ => false,
-                }
-            },
-            _ => false,
+    /// The box the player occupies, the camera is at the eye height.
+    pub fn player_aabb(&self) -> Aabb {
+        let half_width = Self::PLAYER_HALF_WIDTH;
+
+        Aabb::new(
+            self.translation - glam::vec3(half_width, Self::EYE_HEIGHT, half_width),
+            self.translation + glam::vec3(half_width, Self::PLAYER_HEIGHT - Self::EYE_HEIGHT, half_width),
+        )
+    }
+
+    pub fn handle_input(&mut self, input: &InputMap, delta: f32, world: &World) {
+        if input.was_pressed(Action::ToggleMovementMode) {
+            self.movement_mode.toggle();
+            self.velocity = glam::Vec3::ZERO;
+        }
+
+        match self.movement_mode {
+            MovementMode::Flying  => self.fly(input, delta),
+            MovementMode::Walking => self.walk(input, delta, world),
         }
     }
 
-    pub fn handle_pressed_keys(&mut self, delta: f32) {
+    fn fly(&mut self, input: &InputMap, delta: f32) {
         let mut local_speed = glam::Vec3::ZERO;
 
-        if self.pressed_keys[0] {
+        if input.is_active(Action::MoveForward) {
             local_speed += self.speed * self.forward;
         }
-        if self.pressed_keys[1] {
+        if input.is_active(Action::MoveBackward) {
             local_speed += -self.speed * self.forward;
         }
 
-        if self.pressed_keys[2] {
+        if input.is_active(Action::MoveLeft) {
             local_speed += -self.speed * self.right;
         }
-        if self.pressed_keys[3] {
+        if input.is_active(Action::MoveRight) {
             local_speed += self.speed * self.right;
         }
 
-        if self.pressed_keys[4] {
+        if input.is_active(Action::MoveUp) {
             local_speed += self.speed * self.up;
         }
-        if self.pressed_keys[5] {
+        if input.is_active(Action::MoveDown) {
             local_speed += -self.speed * self.up;
         }
         
@@ -126,6 +144,59 @@ impl CameraController {
         self.translation += delta_translation;
     }
 
+    fn walk(&mut self, input: &InputMap, delta: f32, world: &World) {
+        // walk along the ground, no matter where the camera looks
+        let forward = glam::vec3(self.forward.x, 0.0, self.forward.z).normalize_or_zero();
+        let right = glam::vec3(self.right.x, 0.0, self.right.z).normalize_or_zero();
+        let mut direction = glam::Vec3::ZERO;
+
+        if input.is_active(Action::MoveForward) {
+            direction += forward;
+        }
+        if input.is_active(Action::MoveBackward) {
+            direction -= forward;
+        }
+
+        if input.is_active(Action::MoveLeft) {
+            direction -= right;
+        }
+        if input.is_active(Action::MoveRight) {
+            direction += right;
+        }
+
+        if input.is_active(Action::MoveUp) && self.on_ground {
+            self.velocity.y = Self::JUMP_SPEED;
+        }
+
+        self.velocity.y = (self.velocity.y - Self::GRAVITY * delta).max(-Self::MAX_FALL_SPEED);
+
+        let horizontal = direction.normalize_or_zero() * self.speed;
+        let delta_translation = (horizontal + self.velocity) * delta;
+
+        let aabb = self.player_aabb();
+        let (moved, blocked) = aabb.move_and_collide(delta_translation, |block| world.collision_box(block));
+
+        self.on_ground = blocked.y && self.velocity.y < 0.0;
+        if blocked.y {
+            self.velocity.y = 0.0;
+        }
+
+        self.translation += moved.min - aabb.min;
+    }
+
+    /// Turns the camera towards the target, keeping the pitch within the mouse limit.
+    pub fn look_at(&mut self, target: glam::Vec3) {
+        let mut forward = (target - self.translation).normalize_or_zero();
+        if forward == glam::Vec3::ZERO {
+            return;
+        }
+
+        forward.y = forward.y.clamp(-self.mouse_limit, self.mouse_limit);
+
+        self.forward = forward.normalize();
+        self.right = self.forward.cross(self.up).normalize();
+    }
+
     pub fn handle_mouse_input(&mut self, input: &winit::event::DeviceEvent) -> bool {
         match input {
             winit::event::DeviceEvent::MouseMotion { delta } => {
@@ -154,7 +225,7 @@ impl CameraController {
 
 
 pub struct Camera {
-    fov: f32,
+    fov: f32, // vertical, in radians
     aspect: f32,
     near: f32,
     far: f32,
@@ -193,16 +264,45 @@ impl Camera {
     pub fn translation_mut(&mut self) -> &mut glam::Vec3 {
         &mut self.controller.translation
     }
+
+    pub fn forward(&self) -> glam::Vec3 {
+        self.controller.forward
+    }
+
+    pub fn look_at(&mut self, target: glam::Vec3) {
+        self.controller.look_at(target);
+    }
+
+    pub fn movement_mode(&self) -> MovementMode {
+        self.controller.movement_mode
+    }
+
+    pub fn set_movement_mode(&mut self, movement_mode: MovementMode) {
+        self.controller.movement_mode = movement_mode;
+        self.controller.velocity = glam::Vec3::ZERO;
+    }
+
+    pub fn player_aabb(&self) -> Aabb {
+        self.controller.player_aabb()
+    }
+
+    /// The frustum of the view projection last written to the uniform buffer,
+    /// so it matches what gets drawn.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(glam::Mat4::from_cols_array_2d(&self.uniform.view_projection))
+    }
 }
 
 impl Camera {
     fn calculate_aspect(state: &HardwareState) -> f32 {
-        let window_size = state.window().inner_size();
-        window_size.width as f32 / window_size.height as f32
+        let size = state.size();
+        size.width as f32 / size.height as f32
     }
 
     /// args: 
+    ///  - fov: vertical field of view in degrees
     ///  - mouse_limit: Limits camera's pitch rotation, between 0 and 1. (1 = 90 degrees)
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         state: &HardwareState,
         translation: glam::Vec3,
@@ -244,7 +344,7 @@ impl Camera {
         let camera_controller = CameraController::new(translation, sensitivity, speed, mouse_limit);
 
         Camera {
-            fov,
+            fov: fov.to_radians(),
             aspect: Self::calculate_aspect(state),
             near,
             far,
@@ -255,26 +355,34 @@ impl Camera {
         }
     }
 
-    fn build_view_projection(&self) -> glam::Mat4 {
+    pub fn build_view_projection(&self) -> glam::Mat4 {
         let view = glam::Mat4::look_at_rh(self.controller.translation, self.controller.translation + self.controller.forward, self.controller.up);
         let projection = glam::Mat4::perspective_rh_gl(self.fov, self.aspect, self.near, self.far);
 
         projection * view
     }
 
-    pub fn update(&mut self, state: &HardwareState, delta_time: f32) {
+    /// args:
+    ///  - input: moves the camera and toggles the movement mode
+    ///  - world: blocks the player collides with while walking
+    pub fn update(&mut self, state: &HardwareState, input: &InputMap, delta_time: f32, world: &World) {
+        self.update_uniform(state);
+        self.controller.handle_input(input, delta_time, world);
+    }
+
+    fn update_uniform(&mut self, state: &HardwareState) {
         self.uniform.view_projection = self.build_view_projection().to_cols_array_2d();
         state.queue().write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
-        self.controller.handle_pressed_keys(delta_time);
     }
 
     pub fn resize(&mut self, state: &HardwareState) {
         self.aspect = Self::calculate_aspect(state);
-        self.update(state, 0.0);
+        self.update_uniform(state);
     }
 
-    pub fn handle_keyboard_input(&mut self, event: &winit::event::WindowEvent) -> bool {
-        self.controller.handle_keyboard_input(event)
+    /// Returns the block the camera is looking at.
+    pub fn pick_block(&self, world: &World, max_distance: f32) -> Option<RaycastHit> {
+        world.raycast(self.translation(), self.forward(), max_distance)
     }
 
     pub fn handle_mouse_input(&mut self, input: &winit::event::DeviceEvent) -> bool {
@@ -1,56 +1,218 @@
 use bitvec::prelude::*;
 
-use crate::{Array3D, InstanceManager, HardwareState};
+use crate::{Array3D, MeshPools, MeshHandle, ChunkMesh, FaceFormat, HardwareState, Border, ShiftDirection, Instance, PalettedArray3D, BlockId, AIR, BlockRegistry, Aabb, MeshJob, MeshResult, greedy_mesh, packed_mesh};
 
 /// A chunk is a 16x16x16 area of blocks.
 pub struct Chunk {
-    // blocks: Vec<Block>,
-    block_data: Array3D, // 1 block = 1 bit
+    position: glam::IVec3, // in chunk coordinates
+    blocks: PalettedArray3D,
+    block_data: Array3D, // 1 block = 1 bit, any block or air
     faces: Vec<BitVec>, // 6 faces = 6 bitslices
     face_count: u32,
-    instance_manager: InstanceManager,
+    blocks_changed: bool, // block ids changed since the last meshing
+    modified: bool, // block ids changed since the chunk was generated, loaded or saved
+    mesh: Option<MeshHandle>, // in the mesh pools of the chunk map
 }
 
 impl Chunk {
-    pub fn new() -> Self {
-        let block_data = Array3D::new(16);
+    pub fn position(&self) -> glam::IVec3 {
+        self.position
+    }
+
+    pub fn blocks(&self) -> &PalettedArray3D {
+        &self.blocks
+    }
+
+    pub fn block_data(&self) -> &Array3D {
+        &self.block_data
+    }
+
+    pub fn faces(&self) -> &[BitVec] {
+        &self.faces
+    }
+
+    pub fn face_count(&self) -> u32 {
+        self.face_count
+    }
+
+    /// None until the chunk is meshed for the first time.
+    pub fn mesh(&self) -> Option<MeshHandle> {
+        self.mesh
+    }
+
+    /// Whether the blocks differ from what was last generated, loaded or saved.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.modified = false;
+    }
+}
+
+impl Chunk {
+    pub const SIZE: usize = 16;
+
+    pub fn new(position: glam::IVec3) -> Self {
+        let block_data = Array3D::new(Self::SIZE);
         let faces = vec![BitVec::new(); 6];
 
         Self {
+            position,
+            blocks: PalettedArray3D::new(Self::SIZE),
             block_data,
             faces,
             face_count: 0,
-            instance_manager: InstanceManager::new(0),
+            blocks_changed: true,
+            modified: false,
+            mesh: None,
         }
     }
 
-    pub fn generate_faces(&mut self, state: &HardwareState, faces: &[BitVec; 6]) {
-        let current_face_count = self.face_count;
-        let mut new_face_count = 0;
+    /// Splits a world block position into the chunk position and the position inside of that chunk.
+    pub fn split_position(world_position: glam::IVec3) -> (glam::IVec3, glam::UVec3) {
+        let size = Self::SIZE as i32;
+        let chunk = glam::ivec3(world_position.x.div_euclid(size), world_position.y.div_euclid(size), world_position.z.div_euclid(size));
+        let local = world_position - chunk * size;
+
+        (chunk, local.as_uvec3())
+    }
 
-        for side in faces {
-            new_face_count += side.count_ones();
+    /// Creates a chunk from already filled block storage.
+    pub fn from_blocks(position: glam::IVec3, blocks: PalettedArray3D) -> Self {
+        let mut chunk = Self::new(position);
+        chunk.block_data = blocks.solid_mask();
+        chunk.blocks = blocks;
+        chunk.blocks_changed = true;
+
+        chunk
+    }
+
+    pub fn get_block(&self, x: usize, y: usize, z: usize) -> BlockId {
+        self.blocks.get(x, y, z)
+    }
+
+    pub fn is_solid(&self, x: usize, y: usize, z: usize, registry: &BlockRegistry) -> bool {
+        registry.is_solid(self.get_block(x, y, z))
+    }
+
+    /// Mask of the blocks that hide the faces of their neighbors.
+    pub fn opaque_mask(&self, registry: &BlockRegistry) -> Array3D {
+        // most chunks only hold opaque blocks and air
+        if self.blocks.palette().iter().all(|block| *block == AIR || registry.is_opaque(*block)) {
+            return self.block_data.clone();
         }
 
-        let new_face_count = new_face_count as u32;
-        if new_face_count != current_face_count {
-            self.instance_manager.set_instances(state, new_face_count as usize);
+        self.blocks.mask(|block| registry.is_opaque(block))
+    }
+
+    /// Sets the block and returns the previous one.
+    pub fn set_block(&mut self, x: usize, y: usize, z: usize, block: BlockId) -> BlockId {
+        self.block_data.set(x, y, z, block != AIR);
+
+        let previous = self.blocks.set(x, y, z, block);
+        self.blocks_changed |= previous != block;
+        self.modified |= previous != block;
+
+        previous
+    }
+
+    /// Returns the outermost layer of opaque blocks on the given side, used as a `Border::Slice` by the neighbor.
+    pub fn border(&self, side: ShiftDirection, registry: &BlockRegistry) -> BitVec {
+        self.opaque_mask(registry).get_border(side)
+    }
+
+    /// World position of the block with the lowest coordinates.
+    pub fn origin(&self) -> glam::IVec3 {
+        self.position * Self::SIZE as i32
+    }
+
+    /// Bounds of the chunk in world coordinates.
+    pub fn aabb(&self) -> Aabb {
+        let origin = self.origin().as_vec3();
+        Aabb::new(origin, origin + glam::Vec3::splat(Self::SIZE as f32))
+    }
+
+    /// Merges the visible faces into instances, see `greedy_mesh`.
+    pub fn create_instances(&self, faces: &[BitVec; 6]) -> Vec<Instance> {
+        greedy_mesh(Self::SIZE, self.origin(), faces, |x, y, z| self.blocks.get(x, y, z))
+    }
+
+    /// Writes the mesh into the pool of its format, in place of the previous one.
+    fn set_mesh(&mut self, state: &HardwareState, pools: &mut MeshPools, mesh: &ChunkMesh) {
+        self.face_count = mesh.len() as u32;
+        self.mesh = Some(pools.set(state, self.mesh, mesh));
+    }
+
+    /// Frees the mesh of the chunk in the pools, has to be called before the chunk is dropped.
+    pub fn release_mesh(&mut self, pools: &mut MeshPools) {
+        if let Some(handle) = self.mesh.take() {
+            pools.remove(handle);
         }
 
-        self.face_count = new_face_count;
+        // the next mesh has to be uploaded even if the faces are the same
+        self.blocks_changed = true;
+    }
+
+    pub fn generate_faces(&mut self, state: &HardwareState, faces: &[BitVec; 6], format: FaceFormat, pools: &mut MeshPools) {
+        let mesh = match format {
+            FaceFormat::Instanced => ChunkMesh::Instanced(self.create_instances(faces)),
+            FaceFormat::Packed    => ChunkMesh::Packed(packed_mesh(Self::SIZE, faces, |x, y, z| self.blocks.get(x, y, z))),
+        };
+
+        self.set_mesh(state, pools, &mesh);
         self.faces = faces.to_vec();
+        self.blocks_changed = false;
     }
 
-    pub fn update_faces(&mut self, state: &HardwareState) -> [BitVec; 6] {
-        let faces = self.block_data.get_faces();
-       
-        if self.faces[..] == faces[..] {
-            return faces;
+    /// Copies the blocks, so the chunk can be meshed on another thread.
+    ///
+    /// args:
+    ///  - generation: returned with the result, to tell apart jobs of the same chunk
+    ///  - borders: the opaque blocks behind each side of the chunk, ordered the same as `ShiftDirection`
+    ///  - format: of the mesh in the result
+    pub fn mesh_job(&self, generation: u64, borders: [Border; 6], format: FaceFormat) -> MeshJob {
+        MeshJob {
+            position: self.position,
+            generation,
+            blocks: self.blocks.clone(),
+            borders,
+            format,
+        }
+    }
+
+    /// Uploads the mesh of the result, unless neither the faces, the blocks nor the face format changed since the last mesh.
+    /// The result has to come from a job of the chunk's current blocks.
+    pub fn apply_mesh(&mut self, state: &HardwareState, result: MeshResult, pools: &mut MeshPools) {
+        let same_format = self.mesh.map(|handle| handle.format()) == Some(result.mesh.format());
+        if same_format && !self.blocks_changed && self.faces[..] == result.faces[..] {
+            return;
         }
 
-        self.generate_faces(state, &faces);
+        self.set_mesh(state, pools, &result.mesh);
+        self.faces = result.faces.to_vec();
+        self.blocks_changed = false;
+    }
+
+    /// Meshes the chunk on the current thread, see `ChunkMap::update` for meshing on worker threads.
+    ///
+    /// args:
+    ///  - borders: the opaque blocks behind each side of the chunk, ordered the same as `ShiftDirection`
+    ///  - registry: only opaque blocks cull the faces of their neighbors
+    ///  - format: how the faces are stored in the pools
+    ///  - pools: receive the mesh of the chunk
+    pub fn update_faces(
+        &mut self,
+        state: &HardwareState,
+        borders: &[Border; 6],
+        registry: &BlockRegistry,
+        format: FaceFormat,
+        pools: &mut MeshPools,
+    ) -> [BitVec; 6] {
+        let result = self.mesh_job(0, borders.clone(), format).run(registry);
+        let faces = result.faces.clone();
+        self.apply_mesh(state, result, pools);
 
         faces
     }
 }
-
@@ -0,0 +1,431 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::{Chunk, Border, ShiftDirection, HardwareState, MeshPools, ChunkDraws, FaceFormat, BlockId, AIR, BlockRegistry, CollisionShape, Aabb, Frustum, RaycastHit, MeshWorkers, MeshResult, MeshError, raycast};
+
+/// Owns chunks by their chunk coordinates and keeps the faces
+/// on their borders in sync with the neighboring chunks.
+/// Chunks are meshed on worker threads and uploaded during `update`.
+pub struct ChunkMap {
+    chunks: HashMap<glam::IVec3, Chunk>,
+    dirty: HashSet<glam::IVec3>,
+    edited: HashSet<glam::IVec3>, // changed by `set_block`, meshed on the calling thread by the next update
+    registry: Arc<BlockRegistry>,
+    workers: MeshWorkers,
+    pending: HashMap<glam::IVec3, u64>, // generation of the newest mesh job of each chunk
+    next_generation: u64,
+    pools: MeshPools, // meshes of every chunk
+    format: FaceFormat, // of the meshes created from now on
+}
+
+impl ChunkMap {
+    pub fn get(&self, position: glam::IVec3) -> Option<&Chunk> {
+        self.chunks.get(&position)
+    }
+
+    /// Blocks changed through the returned chunk are only re-meshed after calling `mark_dirty`.
+    pub fn get_mut(&mut self, position: glam::IVec3) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&position)
+    }
+
+    pub fn contains(&self, position: glam::IVec3) -> bool {
+        self.chunks.contains_key(&position)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values()
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = &glam::IVec3> {
+        self.chunks.keys()
+    }
+
+    /// The buffers with the meshes of every chunk, drawn with the ranges of `meshes` or `visible_meshes`.
+    pub fn pools(&self) -> &MeshPools {
+        &self.pools
+    }
+
+    pub fn face_format(&self) -> FaceFormat {
+        self.format
+    }
+
+    /// Returns the meshes of the chunks with visible faces for which the filter returns true
+    /// and how many chunks with visible faces it rejected.
+    fn draws(&self, filter: impl Fn(&Chunk) -> bool) -> (ChunkDraws<'_>, usize) {
+        let mut draws = ChunkDraws {
+            pools: &self.pools,
+            instanced: Vec::new(),
+            packed: Vec::new(),
+        };
+        let mut rejected = 0;
+
+        for chunk in self.chunks.values() {
+            let (handle, range) = match chunk.mesh().and_then(|handle| Some((handle, self.pools.range(handle)?))) {
+                Some((handle, range)) if range.start < range.end => (handle, range),
+                _ => continue,
+            };
+
+            if !filter(chunk) {
+                rejected += 1;
+                continue;
+            }
+
+            match handle.format() {
+                FaceFormat::Instanced => draws.instanced.push(range),
+                FaceFormat::Packed    => draws.packed.push((chunk.origin(), range)),
+            }
+        }
+
+        (draws, rejected)
+    }
+
+    /// Returns the meshes of all chunks that have any visible faces.
+    pub fn meshes(&self) -> ChunkDraws<'_> {
+        self.draws(|_| true).0
+    }
+
+    /// Returns the meshes of the chunks with visible faces inside of the frustum
+    /// and how many chunks with visible faces were outside of it.
+    pub fn visible_meshes(&self, frustum: &Frustum) -> (ChunkDraws<'_>, usize) {
+        self.draws(|chunk| frustum.intersects_aabb(&chunk.aabb()))
+    }
+
+    pub fn registry(&self) -> &Arc<BlockRegistry> {
+        &self.registry
+    }
+
+    /// Number of chunks waiting for their mesh.
+    pub fn pending_meshes(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl ChunkMap {
+    /// Most meshes uploaded to the GPU in one `update`, the rest wait for the next one
+    pub const MESH_UPLOADS_PER_UPDATE: usize = 16;
+    /// Most meshes moved in the pools to defragment them in one `update`
+    pub const DEFRAGMENT_MOVES_PER_UPDATE: usize = 4;
+
+    /// args:
+    ///  - registry: decides which blocks are solid and which hide their neighbors
+    pub fn new(registry: Arc<BlockRegistry>) -> Self {
+        let workers = MeshWorkers::new(MeshWorkers::default_thread_count(), registry.clone());
+
+        Self {
+            chunks: HashMap::new(),
+            dirty: HashSet::new(),
+            edited: HashSet::new(),
+            registry,
+            workers,
+            pending: HashMap::new(),
+            next_generation: 0,
+            pools: MeshPools::new(),
+            format: FaceFormat::default(),
+        }
+    }
+
+    fn mark_neighbors_dirty(&mut self, position: glam::IVec3) {
+        for side in 0..6 {
+            let neighbor = position + ShiftDirection::from_number(side).normal();
+
+            if self.chunks.contains_key(&neighbor) {
+                self.dirty.insert(neighbor);
+            }
+        }
+    }
+
+    /// Re-meshes every chunk in the new format, the previous meshes are drawn until they are replaced.
+    pub fn set_face_format(&mut self, format: FaceFormat) {
+        if format != self.format {
+            self.format = format;
+            self.dirty.extend(self.chunks.keys());
+        }
+    }
+
+    /// Schedules the chunk to be re-meshed on the next `update`.
+    pub fn mark_dirty(&mut self, position: glam::IVec3) {
+        if self.chunks.contains_key(&position) {
+            self.dirty.insert(position);
+        }
+    }
+
+    /// Inserts the chunk at its own position, replacing and returning any previous one.
+    pub fn insert(&mut self, chunk: Chunk) -> Option<Chunk> {
+        let position = chunk.position();
+        let mut previous = self.chunks.insert(position, chunk);
+
+        if let Some(previous) = &mut previous {
+            previous.release_mesh(&mut self.pools);
+        }
+        self.pending.remove(&position);
+
+        self.dirty.insert(position);
+        self.mark_neighbors_dirty(position);
+
+        previous
+    }
+
+    pub fn remove(&mut self, position: glam::IVec3) -> Option<Chunk> {
+        let mut chunk = self.chunks.remove(&position)?;
+        chunk.release_mesh(&mut self.pools);
+
+        self.dirty.remove(&position);
+        self.edited.remove(&position);
+        self.pending.remove(&position);
+        self.mark_neighbors_dirty(position);
+
+        Some(chunk)
+    }
+
+    /// Returns the block at the world position, missing chunks are treated as air.
+    pub fn get_block(&self, world_position: glam::IVec3) -> BlockId {
+        let (chunk_position, local) = Chunk::split_position(world_position);
+
+        match self.chunks.get(&chunk_position) {
+            Some(chunk) => chunk.get_block(local.x as usize, local.y as usize, local.z as usize),
+            None => AIR,
+        }
+    }
+
+    pub fn is_solid(&self, world_position: glam::IVec3) -> bool {
+        self.registry.is_solid(self.get_block(world_position))
+    }
+
+    /// The collision box of the block at the world position, None for blocks the player passes through.
+    /// Blocks of chunks that are not loaded are full boxes, so the player does not fall through the ground
+    /// that is still loading or walk out of the loaded world.
+    pub fn collision_box(&self, world_position: glam::IVec3) -> Option<Aabb> {
+        let (chunk_position, _) = Chunk::split_position(world_position);
+
+        if !self.chunks.contains_key(&chunk_position) {
+            return CollisionShape::Full.aabb(world_position);
+        }
+
+        self.registry.collision_box(self.get_block(world_position), world_position)
+    }
+
+    /// Casts a ray in world coordinates across the loaded chunks.
+    pub fn raycast(&self, origin: glam::Vec3, direction: glam::Vec3, max_distance: f32) -> Option<RaycastHit> {
+        raycast(origin, direction, max_distance, |block| self.is_solid(block))
+    }
+
+    /// Sets the block at the world position and schedules the affected chunks for re-meshing,
+    /// unlike loaded chunks they are meshed on the calling thread by the next `update`.
+    /// Returns false if the chunk containing the position is not loaded.
+    pub fn set_block(&mut self, world_position: glam::IVec3, block: BlockId) -> bool {
+        let (chunk_position, local) = Chunk::split_position(world_position);
+
+        let chunk = match self.chunks.get_mut(&chunk_position) {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+
+        if chunk.set_block(local.x as usize, local.y as usize, local.z as usize, block) == block {
+            return true;
+        }
+
+        self.edited.insert(chunk_position);
+
+        // blocks on the border are visible to the neighboring chunks
+        let last = Chunk::SIZE as u32 - 1;
+        for axis in 0..3 {
+            let mut offset = glam::IVec3::ZERO;
+
+            if local[axis] == 0 {
+                offset[axis] = -1;
+            } else if local[axis] == last {
+                offset[axis] = 1;
+            } else {
+                continue;
+            }
+
+            if self.chunks.contains_key(&(chunk_position + offset)) {
+                self.edited.insert(chunk_position + offset);
+            }
+        }
+
+        true
+    }
+
+    /// Returns the borders of the chunk at the given position,
+    /// sides without a loaded neighbor emit their faces.
+    pub fn borders(&self, position: glam::IVec3) -> [Border; 6] {
+        std::array::from_fn(|side| {
+            let side = ShiftDirection::from_number(side);
+
+            match self.chunks.get(&(position + side.normal())) {
+                Some(neighbor) => Border::Slice(neighbor.border(side.opposite(), &self.registry)),
+                None => Border::Emit,
+            }
+        })
+    }
+
+    /// Meshes the chunks with edited blocks right away, so the edits show up in the same frame.
+    /// Sends every other chunk that changed or whose neighbors changed since the last update to the mesh workers,
+    /// uploads up to `MESH_UPLOADS_PER_UPDATE` finished meshes and defragments the pools a bit.
+    pub fn update(&mut self, state: &HardwareState) {
+        for position in std::mem::take(&mut self.edited) {
+            self.mesh_now(state, position);
+        }
+
+        let dirty = std::mem::take(&mut self.dirty);
+
+        for position in dirty {
+            let borders = self.borders(position);
+
+            if let Some(chunk) = self.chunks.get(&position) {
+                let generation = self.next_generation;
+                self.next_generation += 1;
+
+                match self.workers.submit(chunk.mesh_job(generation, borders, self.format)) {
+                    Ok(()) => { self.pending.insert(position, generation); },
+                    Err(error) => log::error!("Unable to mesh chunk {}: {}", position, error),
+                }
+            }
+        }
+
+        let mut uploaded = 0;
+        while uploaded < Self::MESH_UPLOADS_PER_UPDATE {
+            match self.workers.try_receive() {
+                Some(Ok(result)) => uploaded += self.apply_mesh(state, result) as usize,
+                Some(Err(error)) => {
+                    log::error!("{}", error);
+                    self.drop_failed_job(&error);
+                },
+                None => break,
+            }
+        }
+
+        self.pools.defragment(state, Self::DEFRAGMENT_MOVES_PER_UPDATE);
+    }
+
+    /// Meshes and uploads the chunk on the calling thread, results of its pending mesh jobs become stale.
+    fn mesh_now(&mut self, state: &HardwareState, position: glam::IVec3) {
+        let borders = self.borders(position);
+
+        if let Some(chunk) = self.chunks.get_mut(&position) {
+            chunk.update_faces(state, &borders, &self.registry, self.format, &mut self.pools);
+            self.dirty.remove(&position);
+            self.pending.remove(&position);
+        }
+    }
+
+    /// Blocks until every chunk sent to the mesh workers is meshed and uploaded.
+    /// Returns the first failed job, the chunks of failed jobs keep their previous mesh.
+    pub fn wait_for_meshes(&mut self, state: &HardwareState) -> Result<(), MeshError> {
+        while !self.pending.is_empty() {
+            match self.workers.receive() {
+                Ok(result) => { self.apply_mesh(state, result); },
+                Err(error) => {
+                    self.drop_failed_job(&error);
+                    return Err(error);
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops waiting for the results that will never arrive.
+    fn drop_failed_job(&mut self, error: &MeshError) {
+        match error {
+            MeshError::JobPanicked { position, generation } => {
+                if self.pending.get(position) == Some(generation) {
+                    self.pending.remove(position);
+                }
+            },
+            MeshError::Disconnected => self.pending.clear(),
+        }
+    }
+
+    /// Uploads the result if it is the newest one of a loaded chunk, returns whether it was.
+    fn apply_mesh(&mut self, state: &HardwareState, result: MeshResult) -> bool {
+        if self.pending.get(&result.position) != Some(&result.generation) {
+            return false;
+        }
+        self.pending.remove(&result.position);
+
+        match self.chunks.get_mut(&result.position) {
+            Some(chunk) => {
+                chunk.apply_mesh(state, result, &mut self.pools);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl Default for ChunkMap {
+    /// Uses the default block registry.
+    fn default() -> Self {
+        Self::new(Arc::new(BlockRegistry::default()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headless_state() -> HardwareState {
+        pollster::block_on(HardwareState::new_headless(winit::dpi::PhysicalSize::new(1, 1)))
+            .expect("No adapter to run the chunk map tests with")
+    }
+
+    fn has_face(chunks: &ChunkMap, world_position: glam::IVec3, side: ShiftDirection) -> bool {
+        let (chunk_position, local) = Chunk::split_position(world_position);
+        let chunk = chunks.get(chunk_position).unwrap();
+        let size = Chunk::SIZE as u32;
+
+        chunk.faces()[side as usize][(local.x + local.y * size + local.z * size * size) as usize]
+    }
+
+    #[test]
+    fn unloaded_chunks_collide() {
+        let mut chunks = ChunkMap::default();
+        chunks.insert(Chunk::new(glam::IVec3::ZERO));
+
+        assert_eq!(chunks.collision_box(glam::ivec3(3, 3, 3)), None);
+        assert_eq!(
+            chunks.collision_box(glam::ivec3(3, -1, 3)),
+            Some(Aabb::new(glam::vec3(3.0, -1.0, 3.0), glam::vec3(4.0, 0.0, 4.0))),
+        );
+    }
+
+    #[test]
+    fn neighbors_cull_the_shared_faces() {
+        let state = headless_state();
+        let mut chunks = ChunkMap::default();
+
+        let last = Chunk::SIZE as i32 - 1;
+        let left = glam::ivec3(last, 5, 5);
+        let right = glam::ivec3(last + 1, 5, 5);
+
+        chunks.insert(Chunk::new(glam::IVec3::ZERO));
+        chunks.insert(Chunk::new(glam::IVec3::X));
+        chunks.set_block(left, 1);
+        chunks.set_block(right, 1);
+        chunks.update(&state);
+        chunks.wait_for_meshes(&state).unwrap();
+
+        assert!(!has_face(&chunks, left, ShiftDirection::Right));
+        assert!(!has_face(&chunks, right, ShiftDirection::Left));
+        assert!(has_face(&chunks, left, ShiftDirection::Left));
+        assert!(has_face(&chunks, right, ShiftDirection::Right));
+
+        // breaking the block re-meshes the neighbor within the same update
+        chunks.set_block(left, AIR);
+        chunks.update(&state);
+
+        assert!(has_face(&chunks, right, ShiftDirection::Left));
+        assert_eq!(chunks.pending_meshes(), 0);
+    }
+}
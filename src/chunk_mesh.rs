@@ -0,0 +1,165 @@
+use std::ops::Range;
+
+use crate::{HardwareState, Instance, InstancePool, PackedFace, PoolHandle};
+
+
+/// How the faces of the chunks are stored on the GPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FaceFormat {
+    /// Greedy merged rectangles with float attributes, 28 bytes each
+    #[default]
+    Instanced,
+    /// Unit sized faces in 4 bytes each, pulled from a storage buffer by the shader
+    Packed,
+}
+
+impl FaceFormat {
+    pub fn toggle(&mut self) {
+        match self {
+            Self::Instanced => *self = Self::Packed,
+            Self::Packed    => *self = Self::Instanced,
+        }
+    }
+}
+
+
+/// The faces of one chunk in one of the face formats.
+#[derive(Clone, Debug)]
+pub enum ChunkMesh {
+    Instanced(Vec<Instance>),
+    Packed(Vec<PackedFace>),
+}
+
+impl ChunkMesh {
+    pub fn format(&self) -> FaceFormat {
+        match self {
+            Self::Instanced(_) => FaceFormat::Instanced,
+            Self::Packed(_)    => FaceFormat::Packed,
+        }
+    }
+
+    /// Number of instances or packed faces.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Instanced(instances) => instances.len(),
+            Self::Packed(faces)        => faces.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+/// Refers to a chunk mesh in the pool of its face format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MeshHandle {
+    Instanced(PoolHandle),
+    Packed(PoolHandle),
+}
+
+impl MeshHandle {
+    pub fn format(&self) -> FaceFormat {
+        match self {
+            Self::Instanced(_) => FaceFormat::Instanced,
+            Self::Packed(_)    => FaceFormat::Packed,
+        }
+    }
+}
+
+
+/// The meshes of every chunk, in one pool per face format.
+pub struct MeshPools {
+    instanced: InstancePool<Instance>,
+    packed: InstancePool<PackedFace>,
+}
+
+impl MeshPools {
+    pub fn instanced(&self) -> &InstancePool<Instance> {
+        &self.instanced
+    }
+
+    pub fn packed(&self) -> &InstancePool<PackedFace> {
+        &self.packed
+    }
+
+    /// Range of the mesh in the pool of its format.
+    pub fn range(&self, handle: MeshHandle) -> Option<Range<u32>> {
+        match handle {
+            MeshHandle::Instanced(handle) => self.instanced.range(handle),
+            MeshHandle::Packed(handle)    => self.packed.range(handle),
+        }
+    }
+}
+
+impl MeshPools {
+    pub fn new() -> Self {
+        Self {
+            instanced: InstancePool::new("Chunk Instance Pool", wgpu::BufferUsages::VERTEX),
+            packed: InstancePool::new("Chunk Packed Face Pool", wgpu::BufferUsages::STORAGE),
+        }
+    }
+
+    /// Replaces the mesh of the handle, or inserts it if there is none yet or it is of another format.
+    pub fn set(&mut self, state: &HardwareState, handle: Option<MeshHandle>, mesh: &ChunkMesh) -> MeshHandle {
+        match (handle, mesh) {
+            (Some(MeshHandle::Instanced(handle)), ChunkMesh::Instanced(instances)) if self.instanced.replace(state, handle, instances) => {
+                MeshHandle::Instanced(handle)
+            },
+            (Some(MeshHandle::Packed(handle)), ChunkMesh::Packed(faces)) if self.packed.replace(state, handle, faces) => {
+                MeshHandle::Packed(handle)
+            },
+            (handle, mesh) => {
+                if let Some(handle) = handle {
+                    self.remove(handle);
+                }
+
+                match mesh {
+                    ChunkMesh::Instanced(instances) => MeshHandle::Instanced(self.instanced.insert(state, instances)),
+                    ChunkMesh::Packed(faces)        => MeshHandle::Packed(self.packed.insert(state, faces)),
+                }
+            },
+        }
+    }
+
+    pub fn remove(&mut self, handle: MeshHandle) -> bool {
+        match handle {
+            MeshHandle::Instanced(handle) => self.instanced.remove(handle),
+            MeshHandle::Packed(handle)    => self.packed.remove(handle),
+        }
+    }
+
+    /// Splits the moves between the pools, see `InstancePool::defragment`.
+    pub fn defragment(&mut self, state: &HardwareState, max_moves: usize) -> usize {
+        let moved = self.instanced.defragment(state, max_moves);
+        moved + self.packed.defragment(state, max_moves - moved)
+    }
+}
+
+impl Default for MeshPools {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// The chunk meshes to draw in one frame, chunks can be in either face format.
+pub struct ChunkDraws<'a> {
+    pub pools: &'a MeshPools,
+    /// Instance ranges in the instanced pool
+    pub instanced: Vec<Range<u32>>,
+    /// World position of the block at (0, 0, 0) of each chunk and the range of its faces in the packed pool
+    pub packed: Vec<(glam::IVec3, Range<u32>)>,
+}
+
+impl ChunkDraws<'_> {
+    /// Number of chunks to draw.
+    pub fn len(&self) -> usize {
+        self.instanced.len() + self.packed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,109 @@
+use crate::Aabb;
+
+
+/// The volume visible through a camera, bounded by 6 planes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far plane. Each plane is (normal, distance)
+    /// with the normal pointing inside, a point p is inside when dot(normal, p) + distance >= 0
+    planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    pub fn planes(&self) -> &[glam::Vec4; 6] {
+        &self.planes
+    }
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix with the OpenGL depth range of -1 to 1,
+    /// like the one built by `glam::Mat4::perspective_rh_gl`.
+    pub fn from_view_projection(view_projection: glam::Mat4) -> Self {
+        let rows = view_projection.transpose();
+        let (x, y, z, w) = (rows.x_axis, rows.y_axis, rows.z_axis, rows.w_axis);
+
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|plane| {
+            plane / plane.truncate().length()
+        });
+
+        Self { planes }
+    }
+
+    /// Whether any part of the box may be visible, boxes near the corners
+    /// of the frustum can be reported visible even when they are not.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // corner of the box furthest along the normal
+            let corner = glam::Vec3::select(normal.cmpge(glam::Vec3::ZERO), aabb.max, aabb.min);
+
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Camera, HardwareState};
+
+    /// Frustum of a camera at the origin looking down -z with a 45 degree field of view and a square frame.
+    fn known_frustum() -> Frustum {
+        let state = pollster::block_on(HardwareState::new_headless(winit::dpi::PhysicalSize::new(1, 1)))
+            .expect("No adapter to run the frustum tests with");
+
+        let mut camera = Camera::new(&state, glam::Vec3::ZERO, 45.0, 0.1, 100.0, 0.3, 10.0, None);
+        camera.look_at(glam::Vec3::NEG_Z);
+        // writes the view projection into the uniform
+        camera.resize(&state);
+
+        camera.frustum()
+    }
+
+    fn chunk(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::new(glam::vec3(x, y, z), glam::vec3(x, y, z) + glam::Vec3::splat(16.0))
+    }
+
+    #[test]
+    fn planes_point_inside() {
+        let frustum = known_frustum();
+
+        for plane in frustum.planes() {
+            assert!((plane.truncate().length() - 1.0).abs() < 1e-5);
+            assert!(plane.truncate().dot(glam::vec3(0.0, 0.0, -10.0)) + plane.w > 0.0);
+        }
+    }
+
+    #[test]
+    fn chunk_straight_ahead_is_inside() {
+        assert!(known_frustum().intersects_aabb(&chunk(-8.0, -8.0, -40.0)));
+    }
+
+    #[test]
+    fn chunk_behind_is_culled() {
+        assert!(!known_frustum().intersects_aabb(&chunk(-8.0, -8.0, 8.0)));
+    }
+
+    #[test]
+    fn chunk_crossing_a_plane_is_kept() {
+        let frustum = known_frustum();
+
+        // at z = -32 the frustum reaches tan(22.5 degrees) * 32 = 13.3 blocks to the side
+        assert!(frustum.intersects_aabb(&chunk(5.0, -8.0, -40.0)));
+        // the chunk around the camera crosses the near plane
+        assert!(frustum.intersects_aabb(&chunk(-8.0, -8.0, -8.0)));
+        // past the far plane
+        assert!(frustum.intersects_aabb(&chunk(-8.0, -8.0, -108.0)));
+        assert!(!frustum.intersects_aabb(&chunk(-8.0, -8.0, -120.0)));
+    }
+
+    #[test]
+    fn chunk_beside_the_field_of_view_is_culled() {
+        let frustum = known_frustum();
+
+        // the frustum reaches 13.3 blocks to the side at most, a field of view of 45 radians would reach 17.9
+        assert!(!frustum.intersects_aabb(&chunk(15.0, -8.0, -32.0)));
+        assert!(!frustum.intersects_aabb(&chunk(-8.0, 15.0, -32.0)));
+    }
+}
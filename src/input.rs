@@ -0,0 +1,406 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::InputEvent;
+
+use winit::event::{ElementState, MouseButton, VirtualKeyCode, WindowEvent};
+
+
+/// Everything the player can do with a key or mouse button.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    ToggleMovementMode,
+    ToggleWireframe,
+    BreakBlock,
+    PlaceBlock,
+    Screenshot,
+    ToggleFaceFormat,
+    Exit,
+}
+
+impl Action {
+    pub const ALL: [Action; 13] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::ToggleMovementMode,
+        Action::ToggleWireframe,
+        Action::BreakBlock,
+        Action::PlaceBlock,
+        Action::Screenshot,
+        Action::ToggleFaceFormat,
+        Action::Exit,
+    ];
+
+    /// Name of the action in the bindings file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MoveForward        => "move_forward",
+            Self::MoveBackward       => "move_backward",
+            Self::MoveLeft           => "move_left",
+            Self::MoveRight          => "move_right",
+            Self::MoveUp             => "move_up",
+            Self::MoveDown           => "move_down",
+            Self::ToggleMovementMode => "toggle_movement_mode",
+            Self::ToggleWireframe    => "toggle_wireframe",
+            Self::BreakBlock         => "break_block",
+            Self::PlaceBlock         => "place_block",
+            Self::Screenshot         => "screenshot",
+            Self::ToggleFaceFormat   => "toggle_face_format",
+            Self::Exit               => "exit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn key_name(key: VirtualKeyCode) -> &'static str {
+            match key {
+                $(VirtualKeyCode::$key => stringify!($key),)*
+            }
+        }
+
+        fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names! {
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24,
+    Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down, Back, Return, Space, Compose, Caret, Numlock,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    NumpadAdd, NumpadDivide, NumpadDecimal, NumpadComma, NumpadEnter, NumpadEquals,
+    NumpadMultiply, NumpadSubtract, AbntC1, AbntC2, Apostrophe, Apps, Asterisk, At, Ax,
+    Backslash, Calculator, Capital, Colon, Comma, Convert, Equals, Grave, Kana, Kanji,
+    LAlt, LBracket, LControl, LShift, LWin, Mail, MediaSelect, MediaStop, Minus, Mute,
+    MyComputer, NavigateForward, NavigateBackward, NextTrack, NoConvert, OEM102, Period,
+    PlayPause, Plus, Power, PrevTrack, RAlt, RBracket, RControl, RShift, RWin, Semicolon,
+    Slash, Sleep, Stop, Sysrq, Tab, Underline, Unlabeled, VolumeDown, VolumeUp, Wake,
+    WebBack, WebFavorites, WebForward, WebHome, WebRefresh, WebSearch, WebStop, Yen,
+    Copy, Paste, Cut,
+}
+
+
+/// A key or mouse button an action can be bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+impl Binding {
+    /// Keys are written by their winit name (W, Space, LControl),
+    /// mouse buttons as Mouse:Left, Mouse:Right, Mouse:Middle or Mouse:<number>.
+    pub fn name(&self) -> String {
+        match self {
+            Self::Key(key) => key_name(*key).to_string(),
+            Self::Mouse(MouseButton::Left)   => "Mouse:Left".to_string(),
+            Self::Mouse(MouseButton::Right)  => "Mouse:Right".to_string(),
+            Self::Mouse(MouseButton::Middle) => "Mouse:Middle".to_string(),
+            Self::Mouse(MouseButton::Other(button)) => format!("Mouse:{}", button),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.strip_prefix("Mouse:") {
+            Some("Left")   => Some(Self::Mouse(MouseButton::Left)),
+            Some("Right")  => Some(Self::Mouse(MouseButton::Right)),
+            Some("Middle") => Some(Self::Mouse(MouseButton::Middle)),
+            Some(button)   => button.parse().ok().map(|button| Self::Mouse(MouseButton::Other(button))),
+            None => key_from_name(name).map(Self::Key),
+        }
+    }
+}
+
+
+/// Error reading a bindings file or an input recording.
+#[derive(Debug)]
+pub enum InputFileError {
+    Io(std::io::Error),
+    /// Line number, starting at 1, and what is wrong with it
+    Parse(usize, String),
+}
+
+impl std::fmt::Display for InputFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Input file io error: {}", error),
+            Self::Parse(line, message) => write!(f, "Input file error on line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for InputFileError {}
+
+impl From<std::io::Error> for InputFileError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+
+/// Maps actions to keys and mouse buttons and tracks which of them are active.
+/// Feed it the window events, query the actions once per frame and call `end_frame` afterwards.
+pub struct InputMap {
+    bindings: HashMap<Action, Vec<Binding>>,
+    held: HashSet<Binding>,
+    /// Bindings pressed since the last `end_frame`
+    pressed: HashSet<Binding>,
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], |bindings| bindings.as_slice())
+    }
+
+    /// Whether any binding of the action is held down.
+    pub fn is_active(&self, action: Action) -> bool {
+        self.bindings(action).iter().any(|binding| self.held.contains(binding))
+    }
+
+    /// Whether any binding of the action was pressed this frame.
+    pub fn was_pressed(&self, action: Action) -> bool {
+        self.bindings(action).iter().any(|binding| self.pressed.contains(binding))
+    }
+}
+
+impl InputMap {
+    /// No action has a binding.
+    pub fn new() -> Self {
+        Self {
+            bindings: HashMap::new(),
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+        }
+    }
+
+    /// Loads the bindings from the file, actions missing in it keep their default bindings.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, InputFileError> {
+        let mut input_map = Self::default();
+        input_map.parse(&std::fs::read_to_string(path)?)?;
+
+        Ok(input_map)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), InputFileError> {
+        std::fs::write(path, self.to_config())?;
+        Ok(())
+    }
+
+    /// Replaces the bindings of the actions listed in the config.
+    /// Every line has the form `action = binding, binding`, lines starting with # are comments.
+    /// An action without bindings (`action =`) is unbound, listing an action twice is an error.
+    pub fn parse(&mut self, config: &str) -> Result<(), InputFileError> {
+        let mut parsed = HashSet::new();
+
+        for (index, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| InputFileError::Parse(index + 1, message);
+
+            let (name, bindings) = line.split_once('=')
+                .ok_or_else(|| error(format!("expected `action = binding`, found `{}`", line)))?;
+            let action = Action::from_name(name.trim())
+                .ok_or_else(|| error(format!("unknown action `{}`", name.trim())))?;
+
+            if !parsed.insert(action) {
+                return Err(error(format!("`{}` is bound more than once", action.name())));
+            }
+
+            let bindings = match bindings.trim() {
+                "" => Vec::new(),
+                bindings => bindings.split(',')
+                    .map(str::trim)
+                    .map(|binding| match binding {
+                        "" => Err(error("empty binding".to_string())),
+                        binding => Binding::from_name(binding).ok_or_else(|| error(format!("unknown binding `{}`", binding))),
+                    })
+                    .collect::<Result<Vec<Binding>, InputFileError>>()?,
+            };
+
+            self.bindings.insert(action, bindings);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the bindings in the format read by `parse`.
+    pub fn to_config(&self) -> String {
+        let mut config = String::new();
+
+        for action in Action::ALL {
+            let bindings: Vec<String> = self.bindings(action).iter().map(Binding::name).collect();
+            config += &format!("{} = {}\n", action.name(), bindings.join(", "));
+        }
+
+        config
+    }
+
+    /// Adds the binding to the action, keeping its other bindings.
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|bound| *bound != binding);
+        }
+    }
+
+    pub fn clear_bindings(&mut self, action: Action) {
+        self.bindings.remove(&action);
+    }
+
+    /// Returns true if the event was a key or mouse button input.
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        match InputEvent::from_window_event(event) {
+            Some(InputEvent::Binding(binding, state)) => {
+                self.handle_binding(binding, state);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn handle_binding(&mut self, binding: Binding, state: ElementState) {
+        match state {
+            // ignore key repeats, so pressed actions fire once
+            ElementState::Pressed => if self.held.insert(binding) {
+                self.pressed.insert(binding);
+            },
+            ElementState::Released => {
+                self.held.remove(&binding);
+            },
+        }
+    }
+
+    /// Forgets the bindings pressed during this frame.
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+    }
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut input_map = Self::new();
+
+        let bindings = [
+            (Action::MoveForward,        Binding::Key(VirtualKeyCode::W)),
+            (Action::MoveBackward,       Binding::Key(VirtualKeyCode::S)),
+            (Action::MoveLeft,           Binding::Key(VirtualKeyCode::A)),
+            (Action::MoveRight,          Binding::Key(VirtualKeyCode::D)),
+            (Action::MoveUp,             Binding::Key(VirtualKeyCode::Space)),
+            (Action::MoveDown,           Binding::Key(VirtualKeyCode::LControl)),
+            (Action::ToggleMovementMode, Binding::Key(VirtualKeyCode::F)),
+            (Action::ToggleWireframe,    Binding::Key(VirtualKeyCode::R)),
+            (Action::BreakBlock,         Binding::Mouse(MouseButton::Left)),
+            (Action::PlaceBlock,         Binding::Mouse(MouseButton::Right)),
+            (Action::Screenshot,         Binding::Key(VirtualKeyCode::F2)),
+            (Action::ToggleFaceFormat,   Binding::Key(VirtualKeyCode::F3)),
+            (Action::Exit,               Binding::Key(VirtualKeyCode::Escape)),
+        ];
+
+        for (action, binding) in bindings {
+            input_map.bind(action, binding);
+        }
+
+        input_map
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(config: &str) -> Result<InputMap, InputFileError> {
+        let mut input_map = InputMap::default();
+        input_map.parse(config)?;
+
+        Ok(input_map)
+    }
+
+    fn error_line(result: Result<InputMap, InputFileError>) -> usize {
+        match result {
+            Err(InputFileError::Parse(line, _)) => line,
+            Err(error) => panic!("Unexpected error: {}", error),
+            Ok(_) => panic!("The config was accepted"),
+        }
+    }
+
+    #[test]
+    fn shipped_bindings_are_the_defaults() {
+        let input_map = InputMap::load("res/bindings.cfg").unwrap();
+        assert_eq!(input_map.to_config(), InputMap::default().to_config());
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let input_map = parse("# comment\n\n   \n  # indented comment\nexit = Q\n").unwrap();
+
+        assert_eq!(input_map.bindings(Action::Exit), &[Binding::Key(VirtualKeyCode::Q)]);
+        assert_eq!(input_map.bindings(Action::MoveForward), &[Binding::Key(VirtualKeyCode::W)]);
+    }
+
+    #[test]
+    fn mouse_buttons() {
+        let input_map = parse("break_block = Mouse:Middle, Mouse:4\nplace_block = Mouse:Left, E").unwrap();
+
+        assert_eq!(input_map.bindings(Action::BreakBlock), &[Binding::Mouse(MouseButton::Middle), Binding::Mouse(MouseButton::Other(4))]);
+        assert_eq!(input_map.bindings(Action::PlaceBlock), &[Binding::Mouse(MouseButton::Left), Binding::Key(VirtualKeyCode::E)]);
+        assert_eq!(error_line(parse("break_block = Mouse:Fourth")), 1);
+    }
+
+    #[test]
+    fn empty_bindings_unbind_the_action() {
+        let input_map = parse("exit =").unwrap();
+        assert!(input_map.bindings(Action::Exit).is_empty());
+    }
+
+    #[test]
+    fn invalid_lines_are_errors() {
+        assert_eq!(error_line(parse("exit = Escape\nmove_up = Spacebar")), 2);
+        assert_eq!(error_line(parse("# comment\njump = Space")), 2);
+        assert_eq!(error_line(parse("exit Escape")), 1);
+        assert_eq!(error_line(parse("exit = Escape,, Q")), 1);
+        assert_eq!(error_line(parse("exit = Escape\n\nexit = Q")), 3);
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let mut input_map = InputMap::default();
+        input_map.bind(Action::Screenshot, Binding::Mouse(MouseButton::Other(5)));
+        input_map.clear_bindings(Action::MoveDown);
+
+        assert_eq!(parse(&input_map.to_config()).unwrap().to_config(), input_map.to_config());
+    }
+}
@@ -10,16 +10,69 @@ pub use render::*;
 pub mod camera;
 pub use camera::*;
 
+pub mod frustum;
+pub use frustum::*;
+
 pub mod texture;
 pub use texture::*;
 
+pub mod block;
+pub use block::*;
+
 pub mod chunk;
 pub use chunk::*;
 
+pub mod chunk_mesh;
+pub use chunk_mesh::*;
+
+pub mod chunk_map;
+pub use chunk_map::*;
+
+pub mod mesher;
+pub use mesher::*;
+
+pub mod mesh_workers;
+pub use mesh_workers::*;
+
+pub mod world;
+pub use world::*;
+
+pub mod noise;
+pub use noise::*;
+
+pub mod terrain;
+pub use terrain::*;
+
+pub mod region;
+pub use region::*;
+
+pub mod raycast;
+pub use raycast::*;
+
+pub mod physics;
+pub use physics::*;
+
+pub mod input;
+pub use input::*;
+
+pub mod recording;
+pub use recording::*;
+
+pub mod screenshot;
+pub use screenshot::*;
+
 pub mod bitarrays;
 pub use bitarrays::*;
 
-pub async fn init() {
+/// Loaded on start up, the default bindings are used if the file is missing
+pub const BINDINGS_PATH: &str = "res/bindings.cfg";
+pub const SCREENSHOT_DIRECTORY: &str = "screenshots";
+/// Block textures are loaded from <directory>/<name>.png
+pub const TEXTURE_DIRECTORY: &str = "res/textures";
+
+/// args:
+///  - input_source: live input, or input that is recorded or replayed
+pub async fn init(input_source: InputSource) {
     env_logger::init();
 
     let (event_loop, window) = new_window();
@@ -39,8 +92,49 @@ pub async fn init() {
     let mut camera = Camera::new(&state, origin, fov, near_plane, far_plane, mouse_sensitivity, player_speed, mouse_limit);
     camera.resize(&state);
 
+    let block_registry = match BlockRegistry::load(BlockRegistry::PATH) {
+        Ok(block_registry) => block_registry,
+        Err(error) => {
+            log::warn!("Using the default blocks, unable to load {}: {}", BlockRegistry::PATH, error);
+            BlockRegistry::default()
+        },
+    };
+    let block_registry = std::sync::Arc::new(block_registry);
+
+    let render_distance = 3;
+    let seed = 0;
+    let mut world = World::new(render_distance, seed, NoiseHeightmapGenerator::default(), block_registry.clone());
+    world.set_storage(RegionStorage::new("saves/world"));
+    let selected_block = 1;
+
+    let mut input = match InputMap::load(BINDINGS_PATH) {
+        Ok(input) => input,
+        Err(error) => {
+            log::warn!("Using the default bindings, unable to load {}: {}", BINDINGS_PATH, error);
+            InputMap::default()
+        },
+    };
+
+    let mut input_driver = match InputDriver::new(&input_source) {
+        Ok(input_driver) => input_driver,
+        Err(error) => {
+            log::error!("Unable to load the input recording: {}", error);
+            return;
+        },
+    };
+
     let sample_count = 8;
-    let mut renderer = Renderer::new(&state, &[camera.bind_group_layout()], vec![camera.create_bind_group(&state)], vec![], &shader, &ui_shader, sample_count);
+    let block_textures = BlockTextures::new(&state, &block_registry, TEXTURE_DIRECTORY);
+
+    let mut renderer = Renderer::new(
+        &state,
+        &[camera.bind_group_layout(), block_textures.bind_group_layout()],
+        vec![camera.create_bind_group(&state), block_textures.create_bind_group(&state)],
+        vec![],
+        &shader,
+        &ui_shader,
+        sample_count,
+    );
     let start_time = std::time::Instant::now();
     let mut last_frame_time = start_time;
 
@@ -51,33 +145,63 @@ pub async fn init() {
                 window_id,
                 event, 
                 .. 
-            } => if window_id == state.window().id() {
-                if !camera.handle_keyboard_input(&event) {
-                    match event {
-                        winit::event::WindowEvent::KeyboardInput { input, .. } => handle_keyboard_input(&state, &input, control_flow, &mut renderer),
-                        winit::event::WindowEvent::CloseRequested => *control_flow = winit::event_loop::ControlFlow::Exit,
-                        winit::event::WindowEvent::Resized(size) => {
-                            resize(&mut state, &mut camera, &mut renderer, size);
-                        },
-                        winit::event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            resize(&mut state, &mut camera, &mut renderer, *new_inner_size);
-                        },
-                        _ => (),
-                    }
+            } if state.window().is_some_and(|window| window.id() == window_id) => {
+                match event {
+                    winit::event::WindowEvent::KeyboardInput { .. } | winit::event::WindowEvent::MouseInput { .. } => {
+                        if let Some(input_event) = InputEvent::from_window_event(&event) {
+                            input_driver.handle_event(input_event, &mut input, &mut camera);
+                        }
+                    },
+                    winit::event::WindowEvent::CloseRequested => *control_flow = winit::event_loop::ControlFlow::Exit,
+                    winit::event::WindowEvent::Resized(size) => {
+                        resize(&mut state, &mut camera, &mut renderer, size);
+                    },
+                    winit::event::WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        resize(&mut state, &mut camera, &mut renderer, *new_inner_size);
+                    },
+                    _ => (),
                 }
             },
             winit::event::Event::MainEventsCleared => {
+                // replays use the recorded time steps, so they follow the same path on any machine
+                let delta_time = match input_driver.next_frame(&mut input, &mut camera, last_frame_time.elapsed().as_secs_f32()) {
+                    Some(delta_time) => delta_time,
+                    None => {
+                        *control_flow = winit::event_loop::ControlFlow::Exit;
+                        return;
+                    },
+                };
+
                 update(&state, &start_time, &last_frame_time);
-                camera.update(&state, last_frame_time.elapsed().as_secs_f32());
-                state.window().request_redraw();
+                handle_actions(&input, control_flow, &mut renderer, &mut world);
+                handle_block_actions(&state, &input, &camera, &mut world, selected_block);
+                camera.update(&state, &input, delta_time, &world);
+                world.update(&state, camera.translation());
+
+                if input.was_pressed(Action::Screenshot) && !renderer.capture_next_frame(&state) {
+                    log::warn!("Screenshots are not supported on this surface");
+                }
+                save_screenshots(&state, &mut renderer);
+
+                input.end_frame();
+                if let Some(window) = state.window() {
+                    window.request_redraw();
+                }
                 last_frame_time = std::time::Instant::now();
             }
             winit::event::Event::RedrawRequested(_) => {
-                match renderer.render(&state) {
+                let (meshes, culled) = world.visible_meshes(&camera.frustum());
+                let pools = world.chunks().pools();
+                log::debug!(
+                    "Culled {} of {} chunks, instanced: {}, packed: {}",
+                    culled, culled + meshes.len(), pools.instanced().stats(), pools.packed().stats(),
+                );
+
+                match renderer.render(&state, &meshes) {
                     Ok(_) => (),
                     // Reconfigure the surface if it's lost or outdated
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window().inner_size();
+                        let size = state.size();
                         resize(&mut state, &mut camera, &mut renderer, size); 
                     },
                     // The system is out of memory
@@ -87,7 +211,18 @@ pub async fn init() {
                 }
             },
             winit::event::Event::DeviceEvent { event, .. } => {
-                camera.handle_mouse_input(&event);
+                if let Some(input_event) = InputEvent::from_device_event(&event) {
+                    input_driver.handle_event(input_event, &mut input, &mut camera);
+                }
+            },
+            winit::event::Event::LoopDestroyed => {
+                if let Err(error) = world.save() {
+                    log::error!("Unable to save the world: {}", error);
+                }
+
+                if let Err(error) = input_driver.finish() {
+                    log::error!("Unable to save the input recording: {}", error);
+                }
             },
             _ => (),
         }
@@ -108,18 +243,90 @@ pub fn update(
 
 }
 
-pub fn handle_keyboard_input(
-    _state: &HardwareState, 
-    input: &winit::event::KeyboardInput,
+pub fn handle_actions(
+    input: &InputMap,
     control_flow: &mut winit::event_loop::ControlFlow,
     renderer: &mut Renderer,
+    world: &mut World,
 ) {
-    if input.state == winit::event::ElementState::Pressed {
-        match input.virtual_keycode {
-            Some(winit::event::VirtualKeyCode::Escape) => *control_flow = winit::event_loop::ControlFlow::Exit,
-            Some(winit::event::VirtualKeyCode::R) => renderer.toggle_pipeline(),
-            _ => (),
+    if input.was_pressed(Action::Exit) {
+        *control_flow = winit::event_loop::ControlFlow::Exit;
+    }
+
+    if input.was_pressed(Action::ToggleWireframe) {
+        renderer.toggle_pipeline();
+    }
+
+    if input.was_pressed(Action::ToggleFaceFormat) {
+        if renderer.supports_packed_faces() {
+            let mut format = world.chunks().face_format();
+            format.toggle();
+            world.chunks_mut().set_face_format(format);
+            log::info!("Meshing chunks with the {:?} face format", format);
+        } else {
+            log::warn!("Packed faces are not supported on this device");
         }
     }
 }
 
+/// Saves the frames captured for screenshots once the GPU is done copying them.
+pub fn save_screenshots(state: &HardwareState, renderer: &mut Renderer) {
+    while let Some(frame) = renderer.take_captured_frame(state) {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(error) => {
+                log::error!("Unable to capture the frame: {}", error);
+                continue;
+            },
+        };
+        let path = screenshot_path(SCREENSHOT_DIRECTORY);
+
+        match save_png(&path, frame.size, &frame.pixels) {
+            Ok(_) => log::info!("Saved screenshot {}", path.display()),
+            Err(error) => log::error!("Unable to save screenshot {}: {}", path.display(), error),
+        }
+    }
+}
+
+/// Breaking removes the targeted block, placing puts the selected block against the hit face
+/// unless it would collide with the player.
+pub fn handle_block_actions(
+    state: &HardwareState,
+    input: &InputMap,
+    camera: &Camera,
+    world: &mut World,
+    selected_block: BlockId,
+) {
+    const REACH: f32 = 8.0;
+
+    let (break_block, place_block) = (input.was_pressed(Action::BreakBlock), input.was_pressed(Action::PlaceBlock));
+    if !break_block && !place_block {
+        return;
+    }
+
+    let hit = match camera.pick_block(world, REACH) {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    let changed = if break_block {
+        world.set_block(hit.block, AIR)
+    } else if hit.normal != glam::IVec3::ZERO {
+        // the normal is zero when the camera is inside of the block
+        let target = hit.block + hit.normal;
+
+        // a solid block placed inside of the player would trap them
+        let inside_player = world.registry()
+            .collision_box(selected_block, target)
+            .is_some_and(|collision| collision.intersects(&camera.player_aabb()));
+
+        !inside_player && world.set_block(target, selected_block)
+    } else {
+        false
+    };
+
+    // mesh the changed chunks right away, so the change shows up in this frame
+    if changed {
+        world.update_meshes(state);
+    }
+}
@@ -1,5 +1,5 @@
-use renderer::init;
+use renderer::{init, InputSource};
 
 fn main() {
-    pollster::block_on(init());
+    pollster::block_on(init(InputSource::from_args(std::env::args().skip(1))));
 }
@@ -0,0 +1,205 @@
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, mpsc};
+
+use bitvec::prelude::*;
+
+use crate::{Chunk, Border, PalettedArray3D, BlockRegistry, FaceFormat, ChunkMesh, greedy_mesh, packed_mesh};
+
+
+/// Copy of the blocks of a chunk and of what lies behind its sides,
+/// everything needed to mesh the chunk away from the chunk itself.
+#[derive(Clone, Debug)]
+pub struct MeshJob {
+    pub position: glam::IVec3,
+    /// Tells apart jobs of the same chunk, results of older jobs are stale
+    pub generation: u64,
+    pub blocks: PalettedArray3D,
+    /// The opaque blocks behind each side of the chunk, ordered the same as `ShiftDirection`
+    pub borders: [Border; 6],
+    pub format: FaceFormat,
+}
+
+/// Visible faces and the mesh of a chunk, ready to be uploaded to the GPU.
+#[derive(Clone, Debug)]
+pub struct MeshResult {
+    pub position: glam::IVec3,
+    pub generation: u64,
+    pub faces: [BitVec; 6],
+    pub mesh: ChunkMesh,
+}
+
+impl MeshJob {
+    pub fn run(&self, registry: &BlockRegistry) -> MeshResult {
+        let block_data = self.blocks.solid_mask();
+        let opaque = self.blocks.mask(|block| registry.is_opaque(block));
+        let faces = block_data.get_faces_with_occluders(&opaque, &self.borders);
+
+        let block = |x, y, z| self.blocks.get(x, y, z);
+        let mesh = match self.format {
+            FaceFormat::Instanced => ChunkMesh::Instanced(greedy_mesh(Chunk::SIZE, self.position * Chunk::SIZE as i32, &faces, block)),
+            FaceFormat::Packed    => ChunkMesh::Packed(packed_mesh(Chunk::SIZE, &faces, block)),
+        };
+
+        MeshResult {
+            position: self.position,
+            generation: self.generation,
+            faces,
+            mesh,
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeshError {
+    /// The job panicked, its worker thread keeps running the other jobs
+    JobPanicked { position: glam::IVec3, generation: u64 },
+    /// Every worker thread stopped, no more jobs are run
+    Disconnected,
+}
+
+impl std::fmt::Display for MeshError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::JobPanicked { position, generation } => write!(f, "Mesh job {} of chunk {} panicked", generation, position),
+            Self::Disconnected => write!(f, "All mesh worker threads stopped"),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+
+/// Pool of threads running `MeshJob`s, the results are collected on the thread that owns the pool.
+pub struct MeshWorkers {
+    jobs: Option<mpsc::Sender<MeshJob>>, // None only while dropping, closes the channel
+    results: mpsc::Receiver<Result<MeshResult, MeshError>>,
+    threads: Vec<std::thread::JoinHandle<()>>,
+}
+
+impl MeshWorkers {
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+}
+
+impl MeshWorkers {
+    /// args:
+    ///  - thread_count: at least one thread is always started
+    ///  - registry: decides which blocks hide the faces of their neighbors
+    pub fn new(thread_count: usize, registry: Arc<BlockRegistry>) -> Self {
+        let (job_sender, job_receiver) = mpsc::channel::<MeshJob>();
+        let (result_sender, result_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let threads = (0..thread_count.max(1)).map(|index| {
+            let jobs = job_receiver.clone();
+            let results = result_sender.clone();
+            let registry = registry.clone();
+
+            std::thread::Builder::new()
+                .name(format!("mesh worker {}", index))
+                .spawn(move || loop {
+                    // the lock is released before running the job
+                    let job = match jobs.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => return,
+                    };
+
+                    // a panicking job is reported instead of stopping the thread and leaving its result missing
+                    let result = std::panic::catch_unwind(AssertUnwindSafe(|| job.run(&registry)))
+                        .map_err(|_| MeshError::JobPanicked { position: job.position, generation: job.generation });
+
+                    if results.send(result).is_err() {
+                        return;
+                    }
+                })
+                .expect("Unable to start a mesh worker thread")
+        }).collect();
+
+        Self {
+            jobs: Some(job_sender),
+            results: result_receiver,
+            threads,
+        }
+    }
+
+    /// One thread less than the available parallelism, leaving a core for the main thread.
+    pub fn default_thread_count() -> usize {
+        std::thread::available_parallelism()
+            .map(|count| count.get().saturating_sub(1))
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn submit(&self, job: MeshJob) -> Result<(), MeshError> {
+        match &self.jobs {
+            Some(jobs) => jobs.send(job).map_err(|_| MeshError::Disconnected),
+            None => Err(MeshError::Disconnected),
+        }
+    }
+
+    /// Returns a finished result without waiting, if there is any.
+    pub fn try_receive(&self) -> Option<Result<MeshResult, MeshError>> {
+        match self.results.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(MeshError::Disconnected)),
+        }
+    }
+
+    /// Waits until a result is finished.
+    pub fn receive(&self) -> Result<MeshResult, MeshError> {
+        self.results.recv().unwrap_or(Err(MeshError::Disconnected))
+    }
+}
+
+impl Drop for MeshWorkers {
+    fn drop(&mut self) {
+        self.jobs = None;
+
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(generation: u64, borders: [Border; 6]) -> MeshJob {
+        let mut blocks = PalettedArray3D::new(Chunk::SIZE);
+        blocks.set(1, 2, 3, 1);
+
+        MeshJob {
+            position: glam::ivec3(1, 0, -1),
+            generation,
+            blocks,
+            borders,
+            format: FaceFormat::Instanced,
+        }
+    }
+
+    #[test]
+    fn panicking_jobs_are_reported() {
+        let workers = MeshWorkers::new(1, Arc::new(BlockRegistry::default()));
+
+        // a border slice of the wrong size panics while culling
+        let mut borders: [Border; 6] = std::array::from_fn(|_| Border::Emit);
+        borders[0] = Border::Slice(bitvec![0; 3]);
+
+        workers.submit(job(1, borders)).unwrap();
+        assert_eq!(
+            workers.receive().unwrap_err(),
+            MeshError::JobPanicked { position: glam::ivec3(1, 0, -1), generation: 1 },
+        );
+
+        // the only worker thread is still running
+        workers.submit(job(2, std::array::from_fn(|_| Border::Emit))).unwrap();
+        let result = workers.receive().unwrap();
+        assert_eq!(result.generation, 2);
+        assert_eq!(result.mesh.len(), 6);
+    }
+}
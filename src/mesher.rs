@@ -0,0 +1,179 @@
+use bitvec::prelude::*;
+
+use crate::{ShiftDirection, Instance, PackedFace, BlockId};
+
+/// Returns the (normal, u, v) axes of a side, where u and v are the width and height
+/// of its faces. They match the face tables in `res/shader.wgsl`.
+pub fn face_axes(side: ShiftDirection) -> (usize, usize, usize) {
+    match side {
+        ShiftDirection::Front | ShiftDirection::Back => (2, 0, 1),
+        ShiftDirection::Left  | ShiftDirection::Right => (0, 2, 1),
+        ShiftDirection::Top   | ShiftDirection::Bottom => (1, 0, 2),
+    }
+}
+
+/// Packs every visible face into 32 bits, positions are relative to the chunk, so there is no origin.
+/// The size can be at most `PackedFace::MAX_CHUNK_SIZE`.
+pub fn packed_mesh(
+    size: usize,
+    faces: &[BitVec; 6],
+    block: impl Fn(usize, usize, usize) -> BlockId,
+) -> Vec<PackedFace> {
+    assert!(size <= PackedFace::MAX_CHUNK_SIZE, "Packed faces only fit chunks of up to {} blocks", PackedFace::MAX_CHUNK_SIZE);
+
+    let mut packed = Vec::with_capacity(faces.iter().map(|side| side.count_ones()).sum());
+
+    for (face, side) in faces.iter().enumerate() {
+        for index in side.iter_ones() {
+            let (x, y, z) = (index % size, index / size % size, index / size.pow(2));
+            packed.push(PackedFace::new(glam::uvec3(x as u32, y as u32, z as u32), face as u32, block(x, y, z)));
+        }
+    }
+
+    packed
+}
+
+/// Merges coplanar neighboring faces of the same block into rectangles.
+/// args:
+///  - size: length of one side of the face bitmasks
+///  - origin: world position of the block at (0, 0, 0)
+///  - faces: visible faces of each side, as returned by `Array3D::get_faces`
+///  - block: returns the block id at the given position
+pub fn greedy_mesh(
+    size: usize,
+    origin: glam::IVec3,
+    faces: &[BitVec; 6],
+    block: impl Fn(usize, usize, usize) -> BlockId,
+) -> Vec<Instance> {
+    let mut instances = Vec::new();
+    let mut layer: Vec<Option<BlockId>> = vec![None; size.pow(2)];
+
+    for (face, side) in faces.iter().enumerate() {
+        let (normal_axis, u_axis, v_axis) = face_axes(ShiftDirection::from_number(face));
+
+        for depth in 0..size {
+            // collect the faces of one layer, indexed by u + v * size
+            let mut position = [0; 3];
+            position[normal_axis] = depth;
+
+            for v in 0..size {
+                for u in 0..size {
+                    position[u_axis] = u;
+                    position[v_axis] = v;
+
+                    let [x, y, z] = position;
+                    let index = x + y * size + z * size.pow(2);
+
+                    layer[u + v * size] = if side[index] { Some(block(x, y, z)) } else { None };
+                }
+            }
+
+            for v in 0..size {
+                let mut u = 0;
+
+                while u < size {
+                    let current = match layer[u + v * size] {
+                        Some(current) => current,
+                        None => {
+                            u += 1;
+                            continue;
+                        },
+                    };
+
+                    let mut width = 1;
+                    while u + width < size && layer[u + width + v * size] == Some(current) {
+                        width += 1;
+                    }
+
+                    let mut height = 1;
+                    while v + height < size
+                        && (u..u + width).all(|row_u| layer[row_u + (v + height) * size] == Some(current))
+                    {
+                        height += 1;
+                    }
+
+                    for merged_v in v..v + height {
+                        layer[u + merged_v * size..u + width + merged_v * size].fill(None);
+                    }
+
+                    position[u_axis] = u;
+                    position[v_axis] = v;
+                    let block_position = origin + glam::ivec3(position[0] as i32, position[1] as i32, position[2] as i32);
+
+                    instances.push(Instance {
+                        position: block_position.as_vec3().to_array(),
+                        size: [width as f32, height as f32],
+                        face: face as u32,
+                        block_id: current as u32,
+                    });
+
+                    u += width;
+                }
+            }
+        }
+    }
+
+    instances
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Array3D;
+
+    const SIZE: usize = 16;
+
+    /// Greedy meshes the blocks of a chunk at the world origin.
+    fn mesh(blocks: &[((usize, usize, usize), BlockId)]) -> Vec<Instance> {
+        let mut mask = Array3D::new(SIZE);
+        for &((x, y, z), _) in blocks {
+            mask.set(x, y, z, true);
+        }
+
+        greedy_mesh(SIZE, glam::IVec3::ZERO, &mask.get_faces(), |x, y, z| {
+            blocks.iter().find(|(position, _)| *position == (x, y, z)).unwrap().1
+        })
+    }
+
+    /// Position, size and block id of the instances on the top side.
+    fn top_rectangles(instances: &[Instance]) -> Vec<([f32; 3], [f32; 2], u32)> {
+        instances.iter()
+            .filter(|instance| instance.face == ShiftDirection::Top as u32)
+            .map(|instance| (instance.position, instance.size, instance.block_id))
+            .collect()
+    }
+
+    #[test]
+    fn flat_layer_is_one_quad_per_side() {
+        let layer: Vec<_> = (0..SIZE * SIZE).map(|index| ((index % SIZE, 3, index / SIZE), 1)).collect();
+        let instances = mesh(&layer);
+
+        assert_eq!(top_rectangles(&instances), [([0.0, 3.0, 0.0], [16.0, 16.0], 1)]);
+        assert_eq!(instances.len(), 6);
+
+        // one unit sized instance per face would be 10 times more and then some
+        let unit_faces = 2 * SIZE * SIZE + 4 * SIZE;
+        assert!(instances.len() * 10 <= unit_faces);
+    }
+
+    #[test]
+    fn different_blocks_stay_separate() {
+        let instances = mesh(&[((0, 0, 0), 1), ((1, 0, 0), 1), ((2, 0, 0), 2), ((3, 0, 0), 2)]);
+
+        assert_eq!(top_rectangles(&instances), [
+            ([0.0, 0.0, 0.0], [2.0, 1.0], 1),
+            ([2.0, 0.0, 0.0], [2.0, 1.0], 2),
+        ]);
+    }
+
+    #[test]
+    fn l_shape_is_two_rectangles() {
+        let instances = mesh(&[((0, 0, 0), 1), ((1, 0, 0), 1), ((2, 0, 0), 1), ((0, 0, 1), 1), ((0, 0, 2), 1)]);
+
+        assert_eq!(top_rectangles(&instances), [
+            ([0.0, 0.0, 0.0], [3.0, 1.0], 1),
+            ([0.0, 0.0, 1.0], [1.0, 2.0], 1),
+        ]);
+    }
+}
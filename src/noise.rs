@@ -0,0 +1,82 @@
+/// Mixes the lattice point and the seed into a pseudo random number (splitmix64 finalizer).
+fn hash(x: i32, y: i32, seed: u64) -> u64 {
+    let mut value = seed
+        ^ (x as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u32 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+/// One of 8 evenly spread unit gradients, chosen by the hash of the lattice point.
+fn gradient(x: i32, y: i32, seed: u64) -> (f32, f32) {
+    const DIAGONAL: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    const GRADIENTS: [(f32, f32); 8] = [
+        (1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0),
+        (DIAGONAL, DIAGONAL), (-DIAGONAL, DIAGONAL), (DIAGONAL, -DIAGONAL), (-DIAGONAL, -DIAGONAL),
+    ];
+
+    GRADIENTS[(hash(x, y, seed) & 7) as usize]
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// 2D gradient (Perlin) noise, roughly between -1 and 1,
+/// always the same for the same position and seed.
+pub fn gradient_noise_2d(x: f32, y: f32, seed: u64) -> f32 {
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let (ix, iy) = (x0 as i32, y0 as i32);
+    let (fx, fy) = (x - x0, y - y0);
+
+    let dot = |cx: i32, cy: i32, dx: f32, dy: f32| {
+        let (gx, gy) = gradient(ix + cx, iy + cy, seed);
+        gx * dx + gy * dy
+    };
+
+    let n00 = dot(0, 0, fx, fy);
+    let n10 = dot(1, 0, fx - 1.0, fy);
+    let n01 = dot(0, 1, fx, fy - 1.0);
+    let n11 = dot(1, 1, fx - 1.0, fy - 1.0);
+
+    let u = fade(fx);
+    let v = fade(fy);
+
+    // the extremes of 2D gradient noise are at +-sqrt(0.5)
+    lerp(lerp(n00, n10, u), lerp(n01, n11, u), v) * std::f32::consts::SQRT_2
+}
+
+/// Sums octaves of gradient noise, each with a higher frequency and lower amplitude.
+/// args:
+///  - lacunarity: frequency multiplier between octaves
+///  - persistence: amplitude multiplier between octaves
+pub fn fractal_noise_2d(
+    x: f32,
+    y: f32,
+    seed: u64,
+    octaves: u32,
+    frequency: f32,
+    lacunarity: f32,
+    persistence: f32,
+) -> f32 {
+    let mut result = 0.0;
+    let mut frequency = frequency;
+    let mut amplitude = 1.0;
+
+    for octave in 0..octaves {
+        let octave_seed = seed.wrapping_add(octave as u64);
+        result += gradient_noise_2d(x * frequency, y * frequency, octave_seed) * amplitude;
+
+        frequency *= lacunarity;
+        amplitude *= persistence;
+    }
+
+    result
+}
@@ -0,0 +1,168 @@
+/// Axis aligned bounding box in world coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    /// Keeps boxes that touch a block face from counting as overlapping it.
+    const EPSILON: f32 = 1e-4;
+
+    pub fn new(min: glam::Vec3, max: glam::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn translated(&self, offset: glam::Vec3) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    /// Whether the box overlaps the block (x, y, z), which spans to (x + 1, y + 1, z + 1).
+    pub fn intersects_block(&self, block: glam::IVec3) -> bool {
+        let block_min = block.as_vec3();
+        let block_max = block_min + glam::Vec3::ONE;
+
+        (self.min + Self::EPSILON).cmplt(block_max).all() && (self.max - Self::EPSILON).cmpgt(block_min).all()
+    }
+
+    /// Whether the boxes overlap, boxes that only touch do not.
+    pub fn intersects(&self, other: &Self) -> bool {
+        (0..3).all(|axis| self.overlaps_on(other, axis))
+    }
+
+    /// Range of block coordinates the box overlaps on the axis.
+    fn block_range(&self, axis: usize) -> std::ops::RangeInclusive<i32> {
+        (self.min[axis] + Self::EPSILON).floor() as i32..=(self.max[axis] - Self::EPSILON).floor() as i32
+    }
+
+    /// Whether the boxes overlap on the axis, boxes that only touch do not.
+    fn overlaps_on(&self, other: &Self, axis: usize) -> bool {
+        self.min[axis] + Self::EPSILON < other.max[axis] && self.max[axis] - Self::EPSILON > other.min[axis]
+    }
+
+    /// Moves the box along one axis until it would enter the collision box of a block.
+    /// Returns the distance actually moved and whether the box was stopped.
+    fn sweep_axis(&self, axis: usize, distance: f32, collision: &impl Fn(glam::IVec3) -> Option<Aabb>) -> (f32, bool) {
+        if distance == 0.0 {
+            return (0.0, false);
+        }
+
+        // collision boxes smaller than a block can start in the layer the box is already in
+        let layers = if distance > 0.0 {
+            (self.max[axis] - Self::EPSILON).floor() as i32..=(self.max[axis] + distance - Self::EPSILON).floor() as i32
+        } else {
+            (self.min[axis] + distance + Self::EPSILON).floor() as i32..=(self.min[axis] + Self::EPSILON).floor() as i32
+        };
+
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let mut allowed = distance;
+
+        for layer in layers {
+            for u in self.block_range(u_axis) {
+                for v in self.block_range(v_axis) {
+                    let mut block = glam::IVec3::ZERO;
+                    block[axis] = layer;
+                    block[u_axis] = u;
+                    block[v_axis] = v;
+
+                    let other = match collision(block) {
+                        Some(other) if self.overlaps_on(&other, u_axis) && self.overlaps_on(&other, v_axis) => other,
+                        _ => continue,
+                    };
+
+                    if distance > 0.0 && other.min[axis] >= self.max[axis] - Self::EPSILON {
+                        allowed = allowed.min((other.min[axis] - self.max[axis]).max(0.0));
+                    } else if distance < 0.0 && other.max[axis] <= self.min[axis] + Self::EPSILON {
+                        allowed = allowed.max((other.max[axis] - self.min[axis]).min(0.0));
+                    }
+                }
+            }
+        }
+
+        (allowed, allowed != distance)
+    }
+
+    /// Moves the box one axis at a time (y, x, then z), so it slides along the blocks it hits.
+    /// Returns the moved box and for each axis whether the movement was stopped.
+    ///
+    /// args:
+    ///  - collision: the collision box of the block at the given position, None if it can be passed through
+    pub fn move_and_collide(&self, delta: glam::Vec3, collision: impl Fn(glam::IVec3) -> Option<Aabb>) -> (Self, glam::BVec3) {
+        let mut aabb = *self;
+        let mut blocked = [false; 3];
+
+        for axis in [1, 0, 2] {
+            let (moved, stopped) = aabb.sweep_axis(axis, delta[axis], &collision);
+
+            let mut offset = glam::Vec3::ZERO;
+            offset[axis] = moved;
+            aabb = aabb.translated(offset);
+            blocked[axis] = stopped;
+        }
+
+        (aabb, glam::BVec3::new(blocked[0], blocked[1], blocked[2]))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_that_only_touch_do_not_intersect() {
+        let aabb = Aabb::new(glam::vec3(0.2, 1.0, 0.2), glam::vec3(0.8, 2.8, 0.8));
+        let block = |position: glam::IVec3| Aabb::new(position.as_vec3(), position.as_vec3() + glam::Vec3::ONE);
+
+        assert!(aabb.intersects(&block(glam::ivec3(0, 1, 0))));
+        assert!(aabb.intersects(&block(glam::ivec3(0, 2, 0))));
+        assert!(!aabb.intersects(&block(glam::ivec3(0, 0, 0))));
+        assert!(!aabb.intersects(&block(glam::ivec3(1, 1, 0))));
+    }
+
+    fn player_at(feet: glam::Vec3) -> Aabb {
+        Aabb::new(feet - glam::vec3(0.3, 0.0, 0.3), feet + glam::vec3(0.3, 1.8, 0.3))
+    }
+
+    /// Full blocks wherever the filter returns true.
+    fn blocks(is_solid: impl Fn(glam::IVec3) -> bool) -> impl Fn(glam::IVec3) -> Option<Aabb> {
+        move |block| is_solid(block).then(|| Aabb::new(block.as_vec3(), block.as_vec3() + glam::Vec3::ONE))
+    }
+
+    #[test]
+    fn slides_along_a_wall() {
+        // a wall at x = 1 and a floor below y = 0
+        let collision = blocks(|block| block.x == 1 || block.y < 0);
+        let aabb = player_at(glam::vec3(0.5, 0.0, 0.5));
+
+        let (moved, blocked) = aabb.move_and_collide(glam::vec3(0.5, 0.0, 2.0), collision);
+
+        assert!(blocked.x && !blocked.y && !blocked.z);
+        assert!((moved.max.x - 1.0).abs() < 1e-5, "stops at the wall, not at {}", moved.max.x);
+        assert_eq!(moved.min.z, aabb.min.z + 2.0);
+    }
+
+    #[test]
+    fn lands_on_the_floor() {
+        let collision = blocks(|block| block.y < 0);
+        let aabb = player_at(glam::vec3(0.5, 0.4, 0.5));
+
+        let (moved, blocked) = aabb.move_and_collide(glam::vec3(0.2, -1.0, 0.0), collision);
+
+        assert_eq!(blocked, glam::BVec3::new(false, true, false));
+        assert_eq!(moved.min.y, 0.0);
+        assert_eq!(moved.min.x, aabb.min.x + 0.2);
+    }
+
+    #[test]
+    fn fast_moves_do_not_tunnel() {
+        // a one block thick wall at x = 5
+        let collision = blocks(|block| block.x == 5);
+        let aabb = player_at(glam::vec3(0.5, 0.0, 0.5));
+
+        let (moved, blocked) = aabb.move_and_collide(glam::vec3(40.0, 0.0, 0.0), collision);
+
+        assert!(blocked.x);
+        assert!((moved.max.x - 5.0).abs() < 1e-5, "stops at the wall, not at {}", moved.max.x);
+    }
+}
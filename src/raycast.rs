@@ -0,0 +1,129 @@
+/// The first solid block hit by a ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub block: glam::IVec3,
+    /// Normal of the face the ray entered through, zero if the ray started inside of the block
+    pub normal: glam::IVec3,
+    /// Distance from the origin to the hit face
+    pub distance: f32,
+}
+
+/// Largest block coordinate a ray may reach, far enough from the i32 limits
+/// that stepping to the next block can not overflow despite the f32 rounding.
+pub const MAX_RAYCAST_COORDINATE: f32 = (1 << 30) as f32;
+
+/// Walks the blocks along the ray with the Amanatides-Woo DDA
+/// and returns the first one for which `is_solid` returns true.
+/// Block (x, y, z) spans from (x, y, z) to (x + 1, y + 1, z + 1).
+/// Returns None if the origin or the max distance is not finite, the walk would never end,
+/// or if the ray could reach blocks beyond `MAX_RAYCAST_COORDINATE`.
+pub fn raycast(
+    origin: glam::Vec3,
+    direction: glam::Vec3,
+    max_distance: f32,
+    is_solid: impl Fn(glam::IVec3) -> bool,
+) -> Option<RaycastHit> {
+    let direction = direction.normalize_or_zero();
+    if direction == glam::Vec3::ZERO || !origin.is_finite() || !max_distance.is_finite() {
+        return None;
+    }
+
+    if origin.abs().max_element() + max_distance.max(0.0) > MAX_RAYCAST_COORDINATE {
+        return None;
+    }
+
+    let mut block = origin.floor().as_ivec3();
+    let mut step = glam::IVec3::ZERO;
+    // distance along the ray to cross one block on each axis
+    let mut delta = glam::Vec3::splat(f32::INFINITY);
+    // distance along the ray to the next block boundary on each axis
+    let mut next = glam::Vec3::splat(f32::INFINITY);
+
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            delta[axis] = 1.0 / direction[axis];
+            next[axis] = (block[axis] as f32 + 1.0 - origin[axis]) * delta[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            delta[axis] = -1.0 / direction[axis];
+            next[axis] = (origin[axis] - block[axis] as f32) * delta[axis];
+        }
+    }
+
+    let mut normal = glam::IVec3::ZERO;
+    let mut distance = 0.0;
+
+    loop {
+        if is_solid(block) {
+            return Some(RaycastHit { block, normal, distance });
+        }
+
+        let axis = if next.x < next.y && next.x < next.z {
+            0
+        } else if next.y < next.z {
+            1
+        } else {
+            2
+        };
+
+        distance = next[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        block[axis] += step[axis];
+        next[axis] += delta[axis];
+
+        normal = glam::IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_floor(block: glam::IVec3) -> bool {
+        block.y < 0
+    }
+
+    #[test]
+    fn hits_the_face_facing_the_ray() {
+        let hit = raycast(glam::vec3(0.5, 3.5, 0.5), glam::Vec3::NEG_Y, 10.0, is_floor).unwrap();
+
+        assert_eq!(hit.block, glam::ivec3(0, -1, 0));
+        assert_eq!(hit.normal, glam::IVec3::Y);
+        assert_eq!(hit.distance, 3.5);
+    }
+
+    #[test]
+    fn stops_at_the_max_distance() {
+        assert_eq!(raycast(glam::vec3(0.5, 3.5, 0.5), glam::Vec3::NEG_Y, 3.0, is_floor), None);
+        assert_eq!(raycast(glam::vec3(0.5, 3.5, 0.5), glam::Vec3::Y, 100.0, is_floor), None);
+    }
+
+    #[test]
+    fn non_finite_input_does_not_loop_forever() {
+        let origin = glam::vec3(0.5, 3.5, 0.5);
+
+        assert_eq!(raycast(origin, glam::Vec3::Y, f32::INFINITY, is_floor), None);
+        assert_eq!(raycast(origin, glam::Vec3::Y, f32::NAN, is_floor), None);
+        assert_eq!(raycast(glam::Vec3::NAN, glam::Vec3::Y, 10.0, is_floor), None);
+        assert_eq!(raycast(origin, glam::Vec3::NAN, 10.0, is_floor), None);
+    }
+
+    #[test]
+    fn far_origins_do_not_overflow() {
+        let far = 2.0e9;
+
+        assert_eq!(raycast(glam::vec3(far, 0.5, 0.5), glam::Vec3::X, 10.0, |_| false), None);
+        assert_eq!(raycast(glam::vec3(-far, -far, 0.5), glam::Vec3::NEG_ONE, 10.0, |_| false), None);
+        assert_eq!(raycast(glam::vec3(0.5, 0.5, 0.5), glam::Vec3::X, far, |_| false), None);
+
+        // large coordinates within the limit still hit
+        let hit = raycast(glam::vec3(1.0e6 + 0.5, 3.5, 0.5), glam::Vec3::NEG_Y, 10.0, is_floor).unwrap();
+        assert_eq!(hit.block, glam::ivec3(1_000_000, -1, 0));
+    }
+}
@@ -0,0 +1,384 @@
+use std::path::{Path, PathBuf};
+
+use winit::event::ElementState;
+
+use crate::{Binding, InputFileError, InputMap, Camera};
+
+
+/// One input that reached the input map or the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Binding(Binding, ElementState),
+    MouseMotion(f64, f64),
+}
+
+impl InputEvent {
+    /// Written as `press <binding>`, `release <binding>` or `motion <x> <y>`.
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::Binding(binding, ElementState::Pressed)  => format!("press {}", binding.name()),
+            Self::Binding(binding, ElementState::Released) => format!("release {}", binding.name()),
+            Self::MouseMotion(x, y) => format!("motion {} {}", x, y),
+        }
+    }
+
+    pub fn from_line(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+
+        let event = match words.next()? {
+            "press"   => Self::Binding(Binding::from_name(words.next()?)?, ElementState::Pressed),
+            "release" => Self::Binding(Binding::from_name(words.next()?)?, ElementState::Released),
+            "motion"  => Self::MouseMotion(words.next()?.parse().ok()?, words.next()?.parse().ok()?),
+            _ => return None,
+        };
+
+        match words.next() {
+            Some(_) => None,
+            None => Some(event),
+        }
+    }
+
+    pub fn from_window_event(event: &winit::event::WindowEvent) -> Option<Self> {
+        match event {
+            winit::event::WindowEvent::KeyboardInput { input, .. } => {
+                input.virtual_keycode.map(|key| Self::Binding(Binding::Key(key), input.state))
+            },
+            winit::event::WindowEvent::MouseInput { state, button, .. } => Some(Self::Binding(Binding::Mouse(*button), *state)),
+            _ => None,
+        }
+    }
+
+    pub fn from_device_event(event: &winit::event::DeviceEvent) -> Option<Self> {
+        match event {
+            winit::event::DeviceEvent::MouseMotion { delta } => Some(Self::MouseMotion(delta.0, delta.1)),
+            _ => None,
+        }
+    }
+
+    /// Passes the event to whichever of the two handles it.
+    pub fn apply(&self, input: &mut InputMap, camera: &mut Camera) {
+        match self {
+            Self::Binding(binding, state) => input.handle_binding(*binding, *state),
+            Self::MouseMotion(x, y) => {
+                camera.handle_mouse_input(&winit::event::DeviceEvent::MouseMotion { delta: (*x, *y) });
+            },
+        }
+    }
+}
+
+
+/// The events received before one update and the time step that update used.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputFrame {
+    pub delta_time: f32,
+    pub events: Vec<InputEvent>,
+}
+
+
+/// Input events grouped by the frame they were handled in.
+/// Replaying the frames with their time steps moves the camera along the same path.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InputRecording {
+    frames: Vec<InputFrame>,
+    /// Events of the frame that has not ended yet
+    pending: Vec<InputEvent>,
+}
+
+impl InputRecording {
+    pub fn frames(&self) -> &[InputFrame] {
+        &self.frames
+    }
+
+    /// Sum of the time steps of all frames, in seconds.
+    pub fn duration(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.delta_time).sum()
+    }
+}
+
+impl InputRecording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, event: InputEvent) {
+        self.pending.push(event);
+    }
+
+    /// Closes the current frame, the events recorded since the last call belong to it.
+    pub fn end_frame(&mut self, delta_time: f32) {
+        self.frames.push(InputFrame {
+            delta_time,
+            events: std::mem::take(&mut self.pending),
+        });
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, InputFileError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), InputFileError> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    /// Every frame starts with a `frame <delta time>` line followed by one line per event.
+    pub fn parse(text: &str) -> Result<Self, InputFileError> {
+        let mut recording = Self::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(delta_time) = line.strip_prefix("frame ") {
+                let delta_time = delta_time.trim().parse()
+                    .map_err(|_| InputFileError::Parse(index + 1, format!("invalid time step `{}`", delta_time)))?;
+
+                recording.frames.push(InputFrame { delta_time, events: Vec::new() });
+                continue;
+            }
+
+            let event = InputEvent::from_line(line)
+                .ok_or_else(|| InputFileError::Parse(index + 1, format!("invalid event `{}`", line)))?;
+
+            match recording.frames.last_mut() {
+                Some(frame) => frame.events.push(event),
+                None => return Err(InputFileError::Parse(index + 1, "event before the first frame".to_string())),
+            }
+        }
+
+        Ok(recording)
+    }
+
+    /// Writes the ended frames in the format read by `parse`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        for frame in self.frames.iter() {
+            text += &format!("frame {}\n", frame.delta_time);
+
+            for event in frame.events.iter() {
+                text += &event.to_line();
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+
+/// Hands out the frames of a recording one at a time.
+pub struct InputReplay {
+    recording: InputRecording,
+    next_frame: usize,
+}
+
+impl InputReplay {
+    pub fn recording(&self) -> &InputRecording {
+        &self.recording
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_frame >= self.recording.frames.len()
+    }
+}
+
+impl InputReplay {
+    pub fn new(recording: InputRecording) -> Self {
+        Self {
+            recording,
+            next_frame: 0,
+        }
+    }
+
+    /// Applies the events of the next frame and returns its time step,
+    /// None once every frame was replayed.
+    pub fn next_frame(&mut self, input: &mut InputMap, camera: &mut Camera) -> Option<f32> {
+        let frame = self.recording.frames.get(self.next_frame)?;
+        self.next_frame += 1;
+
+        for event in frame.events.iter() {
+            event.apply(input, camera);
+        }
+
+        Some(frame.delta_time)
+    }
+}
+
+
+/// Where the input comes from, chosen with the `--record <file>` and `--replay <file>` arguments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputSource {
+    Live,
+    /// Live input, written to the file on exit
+    Record(PathBuf),
+    /// Input read from the file, live input is ignored and the application exits when it ends
+    Replay(PathBuf),
+}
+
+impl InputSource {
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => if let Some(path) = args.next() {
+                    return Self::Record(path.into());
+                },
+                "--replay" => if let Some(path) = args.next() {
+                    return Self::Replay(path.into());
+                },
+                _ => log::warn!("Unknown argument {}", arg),
+            }
+        }
+
+        Self::Live
+    }
+}
+
+
+/// Passes live input on while recording it, or replaces it with a replayed recording.
+pub struct InputDriver {
+    recording: Option<(PathBuf, InputRecording)>,
+    replay: Option<InputReplay>,
+    replay_start: Option<std::time::Instant>,
+}
+
+impl InputDriver {
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+}
+
+impl InputDriver {
+    /// Loads the recording when replaying.
+    pub fn new(source: &InputSource) -> Result<Self, InputFileError> {
+        let mut driver = Self {
+            recording: None,
+            replay: None,
+            replay_start: None,
+        };
+
+        match source {
+            InputSource::Live => (),
+            InputSource::Record(path) => driver.recording = Some((path.clone(), InputRecording::new())),
+            InputSource::Replay(path) => driver.replay = Some(InputReplay::new(InputRecording::load(path)?)),
+        }
+
+        Ok(driver)
+    }
+
+    /// Handles a live event, it is dropped while replaying.
+    pub fn handle_event(&mut self, event: InputEvent, input: &mut InputMap, camera: &mut Camera) {
+        if self.replay.is_some() {
+            return;
+        }
+
+        if let Some((_, recording)) = &mut self.recording {
+            recording.record(event);
+        }
+
+        event.apply(input, camera);
+    }
+
+    /// Returns the time step the next update should use,
+    /// the measured one unless replaying, None once the replay is over.
+    pub fn next_frame(&mut self, input: &mut InputMap, camera: &mut Camera, delta_time: f32) -> Option<f32> {
+        match &mut self.replay {
+            Some(replay) => {
+                let start = *self.replay_start.get_or_insert_with(std::time::Instant::now);
+                let delta_time = replay.next_frame(input, camera);
+
+                if delta_time.is_none() {
+                    log::info!(
+                        "Replayed {} frames ({:.2}s of input) in {:.2}s",
+                        replay.recording().frames().len(), replay.recording().duration(), start.elapsed().as_secs_f32(),
+                    );
+                }
+
+                delta_time
+            },
+            None => {
+                if let Some((_, recording)) = &mut self.recording {
+                    recording.end_frame(delta_time);
+                }
+
+                Some(delta_time)
+            },
+        }
+    }
+
+    /// Saves the recording, if there is one.
+    pub fn finish(&self) -> Result<(), InputFileError> {
+        match &self.recording {
+            Some((path, recording)) => recording.save(path),
+            None => Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use winit::event::{MouseButton, VirtualKeyCode};
+
+    fn recorded() -> InputRecording {
+        let mut recording = InputRecording::new();
+
+        recording.record(InputEvent::Binding(Binding::Key(VirtualKeyCode::W), ElementState::Pressed));
+        recording.record(InputEvent::MouseMotion(0.1, -3.25e-7));
+        recording.end_frame(1.0 / 60.0);
+
+        // frames without events keep their time step
+        recording.end_frame(0.1);
+
+        recording.record(InputEvent::Binding(Binding::Mouse(MouseButton::Other(7)), ElementState::Pressed));
+        recording.record(InputEvent::Binding(Binding::Key(VirtualKeyCode::W), ElementState::Released));
+        recording.record(InputEvent::MouseMotion(1e300, f64::MIN_POSITIVE));
+        recording.end_frame(0.016_666_668);
+
+        // not part of an ended frame, so not saved
+        recording.record(InputEvent::MouseMotion(1.0, 1.0));
+
+        recording
+    }
+
+    #[test]
+    fn recording_round_trips_exactly() {
+        let recording = recorded();
+        let parsed = InputRecording::parse(&recording.to_text()).unwrap();
+
+        assert_eq!(parsed.frames(), recording.frames());
+        assert_eq!(parsed.frames().len(), 3);
+        assert_eq!(parsed.duration(), recording.duration());
+        assert_eq!(parsed.to_text(), recording.to_text());
+    }
+
+    #[test]
+    fn events_keep_their_frames() {
+        let parsed = InputRecording::parse(&recorded().to_text()).unwrap();
+        let events: Vec<usize> = parsed.frames().iter().map(|frame| frame.events.len()).collect();
+
+        assert_eq!(events, [2, 0, 3]);
+        assert_eq!(parsed.frames()[2].events[0], InputEvent::Binding(Binding::Mouse(MouseButton::Other(7)), ElementState::Pressed));
+    }
+
+    #[test]
+    fn invalid_recordings_are_errors() {
+        for (text, line) in [
+            ("press W\nframe 0.1", 1),
+            ("frame 0.1\npress Nothing", 2),
+            ("frame 0.1\nmotion 1", 2),
+            ("frame 0.1\nmotion 1 2 3", 2),
+            ("# comment\nframe fast", 2),
+        ] {
+            match InputRecording::parse(text) {
+                Err(InputFileError::Parse(error_line, _)) => assert_eq!(error_line, line, "{:?}", text),
+                other => panic!("{:?} gave {:?}", text, other),
+            }
+        }
+    }
+}
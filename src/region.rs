@@ -0,0 +1,428 @@
+use std::path::PathBuf;
+
+use deku::prelude::*;
+
+use crate::{Chunk, PalettedArray3D, BlockArray3D, GroupedBitVec};
+
+
+/// The layout of region files. Kept apart because the code generated by the deku 0.16 derives
+/// rounds bit lengths up with `(len + 7) / 8`, which clippy would flag in every module using them.
+#[allow(clippy::manual_div_ceil)]
+mod layout {
+    use deku::prelude::*;
+
+    /// Start of every region file.
+    #[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+    #[deku(magic = b"BRRG", endian = "little")]
+    pub struct RegionHeader {
+        pub version: u16,
+        /// Chunks along one side of the region
+        pub region_size: u8,
+        /// Blocks along one side of a chunk
+        pub chunk_size: u8,
+    }
+
+    /// Where the payload of one chunk is stored in the file.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, DekuRead, DekuWrite)]
+    #[deku(endian = "little")]
+    pub struct ChunkLocation {
+        /// Byte offset from the start of the file, 0 if the chunk is not stored
+        pub offset: u32,
+        pub length: u32,
+    }
+
+    /// The header followed by one location per chunk of the region,
+    /// indexed by x + y * region_size + z * region_size^2.
+    #[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+    pub struct RegionTable {
+        pub header: RegionHeader,
+        #[deku(count = "(header.region_size as usize).pow(3)")]
+        pub locations: Vec<ChunkLocation>,
+    }
+
+    /// Serialized blocks of one chunk, the first byte is the payload version.
+    #[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+    #[deku(type = "u8", endian = "little")]
+    pub enum ChunkPayload {
+        #[deku(id = "1")]
+        V1 {
+            // the lengths are checked before reading, so a broken file can not cause huge allocations
+            #[deku(assert = "*palette_len as usize <= ChunkPayload::MAX_PALETTE_LEN")]
+            palette_len: u16,
+            #[deku(count = "palette_len")]
+            palette: Vec<u16>,
+            #[deku(assert = "(1..=ChunkPayload::MAX_BITS_PER_BLOCK).contains(bits_per_block)")]
+            bits_per_block: u8,
+            #[deku(assert = "*data_len as usize == ChunkPayload::data_len(*bits_per_block)")]
+            data_len: u32,
+            #[deku(count = "data_len")]
+            data: Vec<u64>,
+        },
+    }
+}
+
+pub use layout::*;
+
+impl ChunkPayload {
+    /// A chunk can not have more distinct blocks than it has blocks
+    pub const MAX_PALETTE_LEN: usize = Chunk::SIZE.pow(3);
+    pub const MAX_BITS_PER_BLOCK: u8 = 16;
+
+    /// Number of u64 chunks holding the indices of a chunk.
+    pub fn data_len(bits_per_block: u8) -> usize {
+        GroupedBitVec::chunk_count(bits_per_block as usize, Chunk::SIZE.pow(3))
+    }
+
+    pub fn from_chunk(chunk: &Chunk) -> Self {
+        let blocks = chunk.blocks();
+        let data = blocks.indices().data().data().to_vec();
+
+        Self::V1 {
+            palette_len: blocks.palette().len() as u16,
+            palette: blocks.palette().to_vec(),
+            bits_per_block: blocks.bits_per_block() as u8,
+            data_len: data.len() as u32,
+            data,
+        }
+    }
+
+    /// Returns None if the payload does not describe a valid chunk.
+    pub fn into_chunk(self, position: glam::IVec3) -> Option<Chunk> {
+        match self {
+            Self::V1 { palette, bits_per_block, data, .. } => {
+                if bits_per_block == 0 || bits_per_block > Self::MAX_BITS_PER_BLOCK {
+                    return None;
+                }
+
+                let data = GroupedBitVec::from_raw(bits_per_block as usize, Chunk::SIZE.pow(3), data)?;
+                let indices = BlockArray3D::from_data(Chunk::SIZE, data)?;
+                let blocks = PalettedArray3D::from_parts(palette, indices)?;
+
+                Some(Chunk::from_blocks(position, blocks))
+            },
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(std::io::Error),
+    Format(DekuError),
+    /// The file was written with a different region or chunk size, or its payload is broken
+    Incompatible(String),
+}
+
+impl std::fmt::Display for RegionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Region io error: {}", error),
+            Self::Format(error) => write!(f, "Region format error: {}", error),
+            Self::Incompatible(message) => write!(f, "Incompatible region: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RegionError {}
+
+impl From<std::io::Error> for RegionError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<DekuError> for RegionError {
+    fn from(error: DekuError) -> Self {
+        Self::Format(error)
+    }
+}
+
+
+/// All stored chunks of one region, held as serialized payloads.
+pub struct Region {
+    payloads: Vec<Option<Vec<u8>>>,
+}
+
+impl Region {
+    pub const VERSION: u16 = 1;
+    /// Chunks along one side of a region
+    pub const SIZE: i32 = 8;
+
+    pub fn new() -> Self {
+        Self {
+            payloads: vec![None; (Self::SIZE as usize).pow(3)],
+        }
+    }
+
+    /// Splits a chunk position into the region position and the index of the chunk inside of it.
+    pub fn split_position(chunk_position: glam::IVec3) -> (glam::IVec3, usize) {
+        let region = glam::ivec3(
+            chunk_position.x.div_euclid(Self::SIZE),
+            chunk_position.y.div_euclid(Self::SIZE),
+            chunk_position.z.div_euclid(Self::SIZE),
+        );
+        let local = (chunk_position - region * Self::SIZE).as_uvec3();
+        let size = Self::SIZE as usize;
+
+        (region, local.x as usize + local.y as usize * size + local.z as usize * size.pow(2))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RegionError> {
+        // the header is checked first, the size of the table depends on it
+        let (_, header) = RegionHeader::from_bytes((bytes, 0))?;
+
+        if header.version != Self::VERSION {
+            return Err(RegionError::Incompatible(format!("version {}", header.version)));
+        }
+
+        if header.region_size as i32 != Self::SIZE || header.chunk_size as usize != Chunk::SIZE {
+            return Err(RegionError::Incompatible(format!(
+                "region size {}, chunk size {}", header.region_size, header.chunk_size
+            )));
+        }
+
+        let (_, table) = RegionTable::from_bytes((bytes, 0))?;
+
+        let mut region = Self::new();
+
+        for (index, location) in table.locations.iter().enumerate() {
+            if location.offset == 0 {
+                continue;
+            }
+
+            let start = location.offset as usize;
+            let end = start + location.length as usize;
+            let payload = bytes.get(start..end)
+                .ok_or_else(|| RegionError::Incompatible(format!("chunk {} is outside of the file", index)))?;
+
+            region.payloads[index] = Some(payload.to_vec());
+        }
+
+        Ok(region)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, RegionError> {
+        let mut table = RegionTable {
+            header: RegionHeader {
+                version: Self::VERSION,
+                region_size: Self::SIZE as u8,
+                chunk_size: Chunk::SIZE as u8,
+            },
+            locations: vec![ChunkLocation::default(); self.payloads.len()],
+        };
+
+        // the table has a fixed size, so the payload offsets are known up front
+        let mut offset = table.to_bytes()?.len();
+        let mut payloads = Vec::new();
+
+        for (location, payload) in table.locations.iter_mut().zip(self.payloads.iter()) {
+            if let Some(payload) = payload {
+                *location = ChunkLocation {
+                    offset: offset as u32,
+                    length: payload.len() as u32,
+                };
+
+                offset += payload.len();
+                payloads.extend_from_slice(payload);
+            }
+        }
+
+        let mut bytes = table.to_bytes()?;
+        bytes.append(&mut payloads);
+
+        Ok(bytes)
+    }
+
+    pub fn get_chunk(&self, chunk_position: glam::IVec3) -> Result<Option<Chunk>, RegionError> {
+        let (_, index) = Self::split_position(chunk_position);
+
+        let payload = match &self.payloads[index] {
+            Some(payload) => payload,
+            None => return Ok(None),
+        };
+
+        let (_, payload) = ChunkPayload::from_bytes((payload, 0))?;
+
+        match payload.into_chunk(chunk_position) {
+            Some(chunk) => Ok(Some(chunk)),
+            None => Err(RegionError::Incompatible(format!("invalid chunk at {}", chunk_position))),
+        }
+    }
+
+    pub fn set_chunk(&mut self, chunk: &Chunk) -> Result<(), RegionError> {
+        let (_, index) = Self::split_position(chunk.position());
+        self.payloads[index] = Some(ChunkPayload::from_chunk(chunk).to_bytes()?);
+
+        Ok(())
+    }
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Saves and loads chunks in region files inside of a directory.
+pub struct RegionStorage {
+    directory: PathBuf,
+}
+
+impl RegionStorage {
+    pub fn directory(&self) -> &std::path::Path {
+        &self.directory
+    }
+}
+
+impl RegionStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn region_path(&self, region_position: glam::IVec3) -> PathBuf {
+        self.directory.join(format!("r.{}.{}.{}.region", region_position.x, region_position.y, region_position.z))
+    }
+
+    /// Reads and parses a whole region file, returns None if none of its chunks were saved.
+    /// Loading many chunks through the region is faster than calling `load_chunk` for each of them.
+    pub fn load_region(&self, region_position: glam::IVec3) -> Result<Option<Region>, RegionError> {
+        match std::fs::read(self.region_path(region_position)) {
+            Ok(bytes) => Ok(Some(Region::from_bytes(&bytes)?)),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Writes the region into a temporary file next to the region file and renames it over the region file,
+    /// so a crash or a full disk while writing leaves the previous region file intact.
+    fn write_region(&self, region_position: glam::IVec3, region: &Region) -> Result<(), RegionError> {
+        let bytes = region.to_bytes()?;
+        std::fs::create_dir_all(&self.directory)?;
+
+        let path = self.region_path(region_position);
+        let temporary_path = path.with_extension("region.tmp");
+
+        let written = std::fs::File::create(&temporary_path).and_then(|mut file| {
+            std::io::Write::write_all(&mut file, &bytes)?;
+            file.sync_all()
+        });
+        if let Err(error) = written.and_then(|_| std::fs::rename(&temporary_path, &path)) {
+            let _ = std::fs::remove_file(&temporary_path);
+            return Err(error.into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns None if the chunk was never saved.
+    pub fn load_chunk(&self, chunk_position: glam::IVec3) -> Result<Option<Chunk>, RegionError> {
+        let (region_position, _) = Region::split_position(chunk_position);
+
+        match self.load_region(region_position)? {
+            Some(region) => region.get_chunk(chunk_position),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_chunk(&self, chunk: &Chunk) -> Result<(), RegionError> {
+        self.save_chunks(std::iter::once(chunk))
+    }
+
+    /// Saves the chunks, writing each touched region file once.
+    pub fn save_chunks<'a>(&self, chunks: impl IntoIterator<Item = &'a Chunk>) -> Result<(), RegionError> {
+        let mut regions: std::collections::HashMap<glam::IVec3, Region> = std::collections::HashMap::new();
+
+        for chunk in chunks {
+            let (region_position, _) = Region::split_position(chunk.position());
+
+            let region = match regions.entry(region_position) {
+                std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(self.load_region(region_position)?.unwrap_or_default())
+                },
+            };
+
+            region.set_chunk(chunk)?;
+        }
+
+        for (region_position, region) in regions.iter() {
+            self.write_region(*region_position, region)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_round_trips_through_a_region() {
+        let mut chunk = Chunk::new(glam::ivec3(-1, 2, 9));
+        chunk.set_block(0, 0, 0, 3);
+        chunk.set_block(15, 7, 2, 1);
+
+        let mut region = Region::new();
+        region.set_chunk(&chunk).unwrap();
+        let region = Region::from_bytes(&region.to_bytes().unwrap()).unwrap();
+
+        let loaded = region.get_chunk(chunk.position()).unwrap().unwrap();
+        assert_eq!(loaded.blocks(), chunk.blocks());
+        assert!(region.get_chunk(glam::ivec3(0, 2, 9)).unwrap().is_none());
+    }
+
+    #[test]
+    fn oversized_palette_is_rejected() {
+        let bytes = [&[1u8][..], &u16::MAX.to_le_bytes()].concat();
+
+        assert!(matches!(ChunkPayload::from_bytes((&bytes, 0)), Err(DekuError::Assertion(_))));
+    }
+
+    #[test]
+    fn oversized_data_is_rejected() {
+        let bytes = [&[1u8][..], &1u16.to_le_bytes(), &0u16.to_le_bytes(), &[1], &u32::MAX.to_le_bytes()].concat();
+
+        assert!(matches!(ChunkPayload::from_bytes((&bytes, 0)), Err(DekuError::Assertion(_))));
+    }
+
+    #[test]
+    fn invalid_bits_per_block_is_rejected() {
+        let bytes = [&[1u8][..], &1u16.to_le_bytes(), &0u16.to_le_bytes(), &[0], &0u32.to_le_bytes()].concat();
+
+        assert!(matches!(ChunkPayload::from_bytes((&bytes, 0)), Err(DekuError::Assertion(_))));
+    }
+
+    #[test]
+    fn failed_write_keeps_the_region_file() {
+        let directory = std::env::temp_dir().join(format!("renderer-region-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let storage = RegionStorage::new(&directory);
+
+        let mut first = Chunk::new(glam::ivec3(1, 0, 0));
+        first.set_block(1, 2, 3, 2);
+        storage.save_chunk(&first).unwrap();
+
+        // the temporary file can not be created where a directory is
+        let temporary_path = storage.region_path(glam::IVec3::ZERO).with_extension("region.tmp");
+        std::fs::create_dir(&temporary_path).unwrap();
+        let mut second = Chunk::new(glam::ivec3(2, 0, 0));
+        second.set_block(0, 0, 0, 1);
+        assert!(storage.save_chunk(&second).is_err());
+
+        let loaded = storage.load_chunk(first.position()).unwrap().unwrap();
+        assert_eq!(loaded.blocks(), first.blocks());
+        assert!(storage.load_chunk(second.position()).unwrap().is_none());
+
+        std::fs::remove_dir(&temporary_path).unwrap();
+        storage.save_chunk(&second).unwrap();
+        assert!(storage.load_chunk(second.position()).unwrap().is_some());
+        assert!(!temporary_path.exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
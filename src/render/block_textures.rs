@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use wgpu::util::DeviceExt;
+
+use crate::{HardwareState, Texture, BlockRegistry, load_png};
+
+
+/// The textures of every block in one texture array, with the layer of each block face
+/// in a storage buffer indexed by block id * 6 + face.
+/// The buffer ends with one more block, whose faces show a checkerboard, the shader
+/// draws the ids missing from the registry with it.
+pub struct BlockTextures {
+    texture: Texture,
+    face_layers: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl BlockTextures {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn face_layers(&self) -> &wgpu::Buffer {
+        &self.face_layers
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+}
+
+impl BlockTextures {
+    pub fn create_bind_group(&self, state: &HardwareState) -> wgpu::BindGroup {
+        state.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Block Textures Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(self.texture.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(self.texture.sampler()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.face_layers.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Size of the placeholder texture used when no image could be loaded
+    const PLACEHOLDER_SIZE: u32 = 16;
+
+    /// Loads `<directory>/<name>.png` for every texture of the registry.
+    /// Images that are missing, broken or of a different size than the first one
+    /// are replaced with a magenta checkerboard.
+    pub fn new(state: &HardwareState, registry: &BlockRegistry, directory: impl AsRef<Path>) -> Self {
+        let mut size = None;
+        let mut images = Vec::new();
+
+        for name in registry.texture_names() {
+            let path = directory.as_ref().join(format!("{}.png", name));
+
+            match load_png(&path) {
+                Ok((image_size, pixels)) if size.is_none() || size == Some(image_size) => {
+                    size = Some(image_size);
+                    images.push(Some(pixels));
+                },
+                Ok((image_size, _)) => {
+                    log::warn!("Texture {} is {}x{}, other block textures are {:?}", path.display(), image_size.width, image_size.height, size);
+                    images.push(None);
+                },
+                Err(error) => {
+                    log::warn!("Unable to load texture {}: {}", path.display(), error);
+                    images.push(None);
+                },
+            }
+        }
+
+        let size = size.unwrap_or(winit::dpi::PhysicalSize::new(Self::PLACEHOLDER_SIZE, Self::PLACEHOLDER_SIZE));
+        let mut layers: Vec<Vec<u8>> = images.into_iter()
+            .map(|image| image.unwrap_or_else(|| placeholder(size)))
+            .collect();
+
+        // the last layer is the texture of missing blocks
+        let missing_layer = layers.len() as u32;
+        layers.push(placeholder(size));
+
+        let texture = Texture::create_texture_array(state, size, &layers, Some("Block Textures"));
+
+        let face_layers = state.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Block Face Layers Buffer"),
+            contents: bytemuck::cast_slice(&face_layers(registry, missing_layer)),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group_layout = state.device().create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Block Textures Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        Self {
+            texture,
+            face_layers,
+            bind_group_layout,
+        }
+    }
+}
+
+
+/// The face layers of the registry followed by a block with the missing layer on every face.
+fn face_layers(registry: &BlockRegistry, missing_layer: u32) -> Vec<u32> {
+    let mut face_layers = registry.face_layers();
+    face_layers.extend([missing_layer; 6]);
+
+    face_layers
+}
+
+/// Magenta and black checkerboard of 2 by 2 squares.
+fn placeholder(size: winit::dpi::PhysicalSize<u32>) -> Vec<u8> {
+    let square = (size.width.max(size.height) / 2).max(1);
+    let mut pixels = Vec::with_capacity((size.width * size.height * 4) as usize);
+
+    for y in 0..size.height {
+        for x in 0..size.width {
+            if (x / square + y / square).is_multiple_of(2) {
+                pixels.extend_from_slice(&[255, 0, 255, 255]);
+            } else {
+                pixels.extend_from_slice(&[0, 0, 0, 255]);
+            }
+        }
+    }
+
+    pixels
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_block_comes_last() {
+        let registry = BlockRegistry::default();
+        let missing_layer = registry.texture_names().len() as u32;
+        let face_layers = face_layers(&registry, missing_layer);
+
+        assert_eq!(face_layers.len(), (registry.len() + 1) * 6);
+        assert_eq!(face_layers[registry.len() * 6..], [missing_layer; 6]);
+        assert!(face_layers[..registry.len() * 6].iter().all(|layer| *layer < missing_layer));
+    }
+}
@@ -0,0 +1,31 @@
+use crate::Descriptable;
+
+
+/// A rectangle of visible block faces.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Instance {
+    /// World position of the block with the lowest coordinates, the block spans from it to position + 1
+    pub position: [f32; 3],
+    /// Width and height of the rectangle in blocks, see `face_axes`
+    pub size: [f32; 2],
+    /// Index of the side, same as `ShiftDirection`
+    pub face: u32,
+    pub block_id: u32,
+}
+
+impl Descriptable for Instance {
+    const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::Instance;
+    const SIZE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
+
+    fn attribs() -> &'static [wgpu::VertexAttribute] {
+        const ATTRIBS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+            1 => Float32x3,
+            2 => Float32x2,
+            3 => Uint32,
+            4 => Uint32,
+        ];
+
+        &ATTRIBS
+    }
+}
@@ -0,0 +1,164 @@
+use crate::HardwareState;
+
+
+/// Instances kept in memory and mirrored in a GPU buffer.
+/// Changes are collected until `upload`, which writes only the changed range,
+/// the buffer is recreated with double the capacity when the instances outgrow it.
+pub struct InstanceBuffer<T: bytemuck::Pod> {
+    instances: Vec<T>,
+    buffer: Option<wgpu::Buffer>,
+    capacity: usize, // in instances
+    changed: Option<std::ops::Range<usize>>, // instances not yet written to the buffer
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+}
+
+impl<T: bytemuck::Pod> InstanceBuffer<T> {
+    pub fn instances(&self) -> &[T] {
+        &self.instances
+    }
+
+    /// Number of live instances, the ones to draw.
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    /// How many instances fit into the buffer before it has to grow.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// None until the first non-empty upload.
+    pub fn buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref()
+    }
+
+    /// Whether there are changes not yet uploaded.
+    pub fn has_changes(&self) -> bool {
+        self.changed.is_some()
+    }
+}
+
+impl<T: bytemuck::Pod> InstanceBuffer<T> {
+    /// Smallest capacity of a created buffer
+    const MIN_CAPACITY: usize = 16;
+
+    /// args:
+    ///  - usage: COPY_DST is always added, the buffer is written with `Queue::write_buffer`
+    pub fn new(label: &'static str, usage: wgpu::BufferUsages) -> Self {
+        Self {
+            instances: Vec::new(),
+            buffer: None,
+            capacity: 0,
+            changed: None,
+            label,
+            usage: usage | wgpu::BufferUsages::COPY_DST,
+        }
+    }
+
+    fn mark_changed(&mut self, range: std::ops::Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        self.changed = Some(match self.changed.take() {
+            Some(changed) => changed.start.min(range.start)..changed.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// Replaces every instance.
+    pub fn set(&mut self, instances: Vec<T>) {
+        self.instances = instances;
+        self.changed = None;
+        self.mark_changed(0..self.instances.len());
+    }
+
+    pub fn push(&mut self, instance: T) {
+        self.instances.push(instance);
+        self.mark_changed(self.instances.len() - 1..self.instances.len());
+    }
+
+    pub fn extend(&mut self, instances: &[T]) {
+        let start = self.instances.len();
+        self.instances.extend_from_slice(instances);
+        self.mark_changed(start..self.instances.len());
+    }
+
+    /// Panics if the index is out of bounds.
+    pub fn replace(&mut self, index: usize, instance: T) {
+        self.instances[index] = instance;
+        self.mark_changed(index..index + 1);
+    }
+
+    /// Removes the instance by moving the last one into its place, so only one instance has to be written.
+    /// Panics if the index is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        let instance = self.instances.swap_remove(index);
+
+        if index < self.instances.len() {
+            self.mark_changed(index..index + 1);
+        }
+
+        instance
+    }
+
+    /// Removed instances are simply no longer drawn, nothing has to be written.
+    pub fn truncate(&mut self, len: usize) {
+        self.instances.truncate(len);
+
+        if let Some(changed) = self.changed.take() {
+            self.mark_changed(changed.start..changed.end.min(len));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    /// Writes the changed instances into the buffer, growing it first if they do not fit.
+    pub fn upload(&mut self, state: &HardwareState) {
+        let changed = match self.changed.take() {
+            Some(changed) => changed,
+            None => return,
+        };
+
+        if self.instances.len() > self.capacity {
+            self.grow(state);
+            // the new buffer is empty
+            self.write(state, 0..self.instances.len());
+        } else {
+            self.write(state, changed);
+        }
+    }
+
+    fn grow(&mut self, state: &HardwareState) {
+        let mut capacity = self.capacity.max(Self::MIN_CAPACITY);
+        while capacity < self.instances.len() {
+            capacity *= 2;
+        }
+
+        if let Some(buffer) = self.buffer.take() {
+            buffer.destroy();
+        }
+
+        self.buffer = Some(state.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some(self.label),
+            size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: self.usage,
+            mapped_at_creation: false,
+        }));
+        self.capacity = capacity;
+    }
+
+    fn write(&self, state: &HardwareState, range: std::ops::Range<usize>) {
+        if let Some(buffer) = &self.buffer {
+            let offset = (range.start * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+            state.queue().write_buffer(buffer, offset, bytemuck::cast_slice(&self.instances[range]));
+        }
+    }
+}
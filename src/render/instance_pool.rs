@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::HardwareState;
+
+
+/// Hands out ranges of a fixed capacity, always the lowest free range that fits (first fit).
+/// Freed ranges are merged with their free neighbors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeAllocator {
+    capacity: u32,
+    free: Vec<Range<u32>>, // sorted by start, never touching each other
+}
+
+impl RangeAllocator {
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn free_ranges(&self) -> &[Range<u32>] {
+        &self.free
+    }
+
+    pub fn free_len(&self) -> u32 {
+        self.free.iter().map(|range| range.len() as u32).sum()
+    }
+
+    pub fn largest_free_len(&self) -> u32 {
+        self.free.iter().map(|range| range.len() as u32).max().unwrap_or(0)
+    }
+
+    /// Whether all of the free space is in one range at the end.
+    pub fn is_compact(&self) -> bool {
+        match self.free[..] {
+            [] => true,
+            [ref range] => range.end == self.capacity,
+            _ => false,
+        }
+    }
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: u32) -> Self {
+        let mut allocator = Self {
+            capacity: 0,
+            free: Vec::new(),
+        };
+        allocator.grow(capacity);
+
+        allocator
+    }
+
+    /// Returns None if no free range is long enough, the length has to be more than 0.
+    pub fn allocate(&mut self, len: u32) -> Option<Range<u32>> {
+        let index = self.free.iter().position(|range| range.len() as u32 >= len)?;
+        let range = &mut self.free[index];
+
+        let allocated = range.start..range.start + len;
+        range.start += len;
+
+        if range.start == range.end {
+            self.free.remove(index);
+        }
+
+        Some(allocated)
+    }
+
+    /// The range has to be one returned by `allocate`, or a part of one.
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let index = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(index, range);
+
+        // merge with the following range, then with the preceding one
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            self.free[index].end = self.free.remove(index + 1).end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            self.free[index - 1].end = self.free.remove(index).end;
+        }
+    }
+
+    /// Adds free space at the end, the capacity can not shrink.
+    pub fn grow(&mut self, capacity: u32) {
+        if capacity > self.capacity {
+            let added = self.capacity..capacity;
+            self.capacity = capacity;
+            self.free(added);
+        }
+    }
+}
+
+
+/// Refers to the instances inserted into an `InstancePool`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PoolHandle(u64);
+
+/// Allocation statistics of an `InstancePool`, all in instances.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolStats {
+    pub capacity: u32,
+    pub used: u32,
+    pub free: u32,
+    pub allocations: usize,
+    pub free_ranges: usize,
+    pub largest_free: u32,
+}
+
+impl PoolStats {
+    /// 0 when all of the free space is in one range, approaching 1 as it gets split into more small ranges.
+    pub fn fragmentation(&self) -> f32 {
+        match self.free {
+            0 => 0.0,
+            free => 1.0 - self.largest_free as f32 / free as f32,
+        }
+    }
+}
+
+impl std::fmt::Display for PoolStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "{} of {} instances used in {} allocations, {} free ranges, {:.0}% fragmented",
+            self.used, self.capacity, self.allocations, self.free_ranges, self.fragmentation() * 100.0,
+        )
+    }
+}
+
+
+/// One large GPU buffer shared by many meshes, each mesh gets a range of instances.
+/// The buffer doubles its capacity when an allocation does not fit
+/// and `defragment` moves the ranges down to close the gaps between them.
+/// Both copy the instances on the GPU, nothing is kept on the CPU.
+pub struct InstancePool<T: bytemuck::Pod> {
+    buffer: Option<wgpu::Buffer>,
+    allocator: RangeAllocator,
+    allocations: HashMap<PoolHandle, Range<u32>>,
+    next_handle: u64,
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    _instance: std::marker::PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> InstancePool<T> {
+    /// None until the first instances are inserted.
+    pub fn buffer(&self) -> Option<&wgpu::Buffer> {
+        self.buffer.as_ref()
+    }
+
+    /// Range of instances in the buffer, to be passed to the draw call.
+    pub fn range(&self, handle: PoolHandle) -> Option<Range<u32>> {
+        self.allocations.get(&handle).cloned()
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.allocator.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.allocations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let free = self.allocator.free_len();
+
+        PoolStats {
+            capacity: self.capacity(),
+            used: self.capacity() - free,
+            free,
+            allocations: self.allocations.len(),
+            free_ranges: self.allocator.free_ranges().len(),
+            largest_free: self.allocator.largest_free_len(),
+        }
+    }
+}
+
+impl<T: bytemuck::Pod> InstancePool<T> {
+    /// Smallest capacity of a created buffer
+    const MIN_CAPACITY: u32 = 1024;
+    const INSTANCE_SIZE: wgpu::BufferAddress = std::mem::size_of::<T>() as wgpu::BufferAddress;
+
+    /// args:
+    ///  - usage: COPY_SRC and COPY_DST are always added, the instances are written with `Queue::write_buffer` and moved with buffer copies
+    pub fn new(label: &'static str, usage: wgpu::BufferUsages) -> Self {
+        // buffer copies work in steps of 4 bytes
+        assert!(Self::INSTANCE_SIZE % wgpu::COPY_BUFFER_ALIGNMENT == 0, "The instance size has to be a multiple of 4 bytes");
+
+        Self {
+            buffer: None,
+            allocator: RangeAllocator::new(0),
+            allocations: HashMap::new(),
+            next_handle: 0,
+            label,
+            usage: usage | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            _instance: std::marker::PhantomData,
+        }
+    }
+
+    /// Copies the instances into the pool, growing it if they do not fit.
+    pub fn insert(&mut self, state: &HardwareState, instances: &[T]) -> PoolHandle {
+        let handle = PoolHandle(self.next_handle);
+        self.next_handle += 1;
+
+        let range = self.allocate(state, instances.len() as u32);
+        self.write(state, range.clone(), instances);
+        self.allocations.insert(handle, range);
+
+        handle
+    }
+
+    /// Replaces the instances of the handle, in place if they are not longer than the previous ones.
+    /// Returns false if the handle was already removed.
+    pub fn replace(&mut self, state: &HardwareState, handle: PoolHandle, instances: &[T]) -> bool {
+        let previous = match self.allocations.get(&handle) {
+            Some(range) => range.clone(),
+            None => return false,
+        };
+
+        let len = instances.len() as u32;
+        let range = if len <= previous.len() as u32 {
+            self.allocator.free(previous.start + len..previous.end);
+            previous.start..previous.start + len
+        } else {
+            self.allocator.free(previous);
+            self.allocate(state, len)
+        };
+
+        self.write(state, range.clone(), instances);
+        self.allocations.insert(handle, range);
+
+        true
+    }
+
+    /// Frees the range of the handle, returns false if it was already removed.
+    pub fn remove(&mut self, handle: PoolHandle) -> bool {
+        match self.allocations.remove(&handle) {
+            Some(range) => {
+                self.allocator.free(range);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Moves up to `max_moves` ranges to the lowest free space they fit in, starting from the lowest range.
+    /// Meant to be called every frame with a small budget, so the pool gets compact over time.
+    /// Returns how many ranges were moved.
+    pub fn defragment(&mut self, state: &HardwareState, max_moves: usize) -> usize {
+        if max_moves == 0 || self.allocator.is_compact() {
+            return 0;
+        }
+
+        let mut allocations: Vec<(PoolHandle, Range<u32>)> = self.allocations.iter()
+            .filter(|(_, range)| range.start < range.end)
+            .map(|(handle, range)| (*handle, range.clone()))
+            .collect();
+        allocations.sort_by_key(|(_, range)| range.start);
+
+        let mut moves = Vec::new();
+        for (handle, range) in allocations {
+            if moves.len() == max_moves {
+                break;
+            }
+
+            // freeing first merges the range with the gap right below it, the new range may overlap it
+            let len = range.len() as u32;
+            self.allocator.free(range.clone());
+            let moved = self.allocator.allocate(len).expect("The freed range always fits");
+
+            if moved.start < range.start {
+                self.allocations.insert(handle, moved.clone());
+                moves.push((range.start, moved.start, len));
+            }
+        }
+
+        self.copy_moves(state, &moves);
+
+        moves.len()
+    }
+
+    /// Copies the instances of each (from, to, len) move within the buffer.
+    /// A buffer can not be copied into itself, so every source is first copied into a scratch buffer,
+    /// which also keeps overlapping moves from reading instances another move already overwrote.
+    fn copy_moves(&self, state: &HardwareState, moves: &[(u32, u32, u32)]) {
+        let buffer = match (&self.buffer, moves.is_empty()) {
+            (Some(buffer), false) => buffer,
+            _ => return,
+        };
+
+        let scratch_len: u32 = moves.iter().map(|(_, _, len)| len).sum();
+        let scratch = state.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Pool Scratch Buffer"),
+            size: scratch_len as wgpu::BufferAddress * Self::INSTANCE_SIZE,
+            usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = state.device().create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Instance Pool Defragment Encoder"),
+        });
+
+        let bytes = |instances: u32| instances as wgpu::BufferAddress * Self::INSTANCE_SIZE;
+
+        let mut offset = 0;
+        for (from, _, len) in moves {
+            encoder.copy_buffer_to_buffer(buffer, bytes(*from), &scratch, bytes(offset), bytes(*len));
+            offset += len;
+        }
+
+        let mut offset = 0;
+        for (_, to, len) in moves {
+            encoder.copy_buffer_to_buffer(&scratch, bytes(offset), buffer, bytes(*to), bytes(*len));
+            offset += len;
+        }
+
+        state.queue().submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Allocates the range, growing the pool if needed.
+    fn allocate(&mut self, state: &HardwareState, len: u32) -> Range<u32> {
+        if len == 0 {
+            return 0..0;
+        }
+
+        if let Some(range) = self.allocator.allocate(len) {
+            return range;
+        }
+
+        self.grow(state, len);
+        self.allocator.allocate(len).expect("The pool grew to fit the allocation")
+    }
+
+    /// Doubles the capacity until there is a free range of at least `len` instances,
+    /// the old buffer is copied into the new one on the GPU.
+    fn grow(&mut self, state: &HardwareState, len: u32) {
+        let mut capacity = self.capacity().max(Self::MIN_CAPACITY);
+        // the free range at the end grows together with the capacity
+        let free_at_end = self.allocator.free_ranges().last()
+            .filter(|range| range.end == self.capacity())
+            .map_or(0, |range| range.len() as u32);
+
+        while capacity - self.capacity() + free_at_end < len {
+            capacity *= 2;
+        }
+
+        let buffer = state.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some(self.label),
+            size: capacity as wgpu::BufferAddress * Self::INSTANCE_SIZE,
+            usage: self.usage,
+            mapped_at_creation: false,
+        });
+
+        // writes queued for the old buffer are done before the copy, they are submitted first
+        if let Some(old) = self.buffer.take() {
+            let mut encoder = state.device().create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Instance Pool Grow Encoder"),
+            });
+            encoder.copy_buffer_to_buffer(&old, 0, &buffer, 0, old.size());
+            state.queue().submit(std::iter::once(encoder.finish()));
+        }
+
+        self.allocator.grow(capacity);
+        self.buffer = Some(buffer);
+    }
+
+    fn write(&self, state: &HardwareState, range: Range<u32>, instances: &[T]) {
+        if let (Some(buffer), true) = (&self.buffer, range.start < range.end) {
+            let offset = range.start as wgpu::BufferAddress * Self::INSTANCE_SIZE;
+            state.queue().write_buffer(buffer, offset, bytemuck::cast_slice(instances));
+        }
+    }
+}
+
+
+#[cfg(test)]
+// one element arrays of free ranges are intended
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_first_fit() {
+        let mut allocator = RangeAllocator::new(10);
+
+        assert_eq!(allocator.allocate(4), Some(0..4));
+        assert_eq!(allocator.allocate(4), Some(4..8));
+        assert_eq!(allocator.allocate(3), None);
+        assert_eq!(allocator.allocate(2), Some(8..10));
+        assert_eq!(allocator.free_len(), 0);
+
+        allocator.free(0..4);
+        assert_eq!(allocator.allocate(1), Some(0..1));
+        assert_eq!(allocator.free_ranges(), &[1..4]);
+    }
+
+    #[test]
+    fn free_merges_with_both_neighbors() {
+        let mut allocator = RangeAllocator::new(9);
+        let ranges = [allocator.allocate(3).unwrap(), allocator.allocate(3).unwrap(), allocator.allocate(3).unwrap()];
+
+        allocator.free(ranges[0].clone());
+        allocator.free(ranges[2].clone());
+        assert_eq!(allocator.free_ranges(), &[0..3, 6..9]);
+        assert_eq!(allocator.largest_free_len(), 3);
+
+        allocator.free(ranges[1].clone());
+        assert_eq!(allocator.free_ranges(), &[0..9]);
+        assert!(allocator.is_compact());
+    }
+
+    #[test]
+    fn grow_extends_the_free_tail() {
+        let mut allocator = RangeAllocator::new(8);
+        allocator.allocate(6).unwrap();
+
+        allocator.grow(16);
+        assert_eq!(allocator.capacity(), 16);
+        assert_eq!(allocator.free_ranges(), &[6..16]);
+
+        // without a free tail the added space is a range of its own
+        allocator.allocate(10).unwrap();
+        allocator.free(0..2);
+        allocator.grow(20);
+        assert_eq!(allocator.free_ranges(), &[0..2, 16..20]);
+
+        // the capacity never shrinks
+        allocator.grow(4);
+        assert_eq!(allocator.capacity(), 20);
+    }
+
+    #[test]
+    fn fragmentation_of_split_free_space() {
+        let mut allocator = RangeAllocator::new(8);
+        let ranges: Vec<Range<u32>> = (0..4).map(|_| allocator.allocate(2).unwrap()).collect();
+        allocator.free(ranges[0].clone());
+        allocator.free(ranges[2].clone());
+
+        let stats = PoolStats {
+            capacity: allocator.capacity(),
+            used: allocator.capacity() - allocator.free_len(),
+            free: allocator.free_len(),
+            allocations: 2,
+            free_ranges: allocator.free_ranges().len(),
+            largest_free: allocator.largest_free_len(),
+        };
+        assert_eq!(stats.fragmentation(), 0.5);
+    }
+
+
+    fn headless_state() -> HardwareState {
+        pollster::block_on(HardwareState::new_headless(winit::dpi::PhysicalSize::new(1, 1)))
+            .expect("No adapter to run the instance pool tests with")
+    }
+
+    /// Reads the instances of the handle back from the GPU.
+    fn read(state: &HardwareState, pool: &InstancePool<u32>, handle: PoolHandle) -> Vec<u32> {
+        let range = pool.range(handle).unwrap();
+        let size = range.len() as wgpu::BufferAddress * 4;
+        if size == 0 {
+            return Vec::new();
+        }
+
+        let readback = state.device().create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = state.device().create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(pool.buffer().unwrap(), range.start as wgpu::BufferAddress * 4, &readback, 0, size);
+        state.queue().submit(std::iter::once(encoder.finish()));
+
+        readback.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        state.device().poll(wgpu::Maintain::Wait);
+        let instances = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+
+        instances
+    }
+
+    fn numbers(start: u32, len: u32) -> Vec<u32> {
+        (start..start + len).collect()
+    }
+
+    #[test]
+    fn grow_keeps_the_instances() {
+        let state = headless_state();
+        let mut pool = InstancePool::new("Test Pool", wgpu::BufferUsages::STORAGE);
+
+        let first = pool.insert(&state, &numbers(0, 1000));
+        let second = pool.insert(&state, &numbers(5000, 1000));
+
+        assert_eq!(pool.capacity(), 2048);
+        assert_eq!(read(&state, &pool, first), numbers(0, 1000));
+        assert_eq!(read(&state, &pool, second), numbers(5000, 1000));
+    }
+
+    #[test]
+    fn replace_shrinks_in_place() {
+        let state = headless_state();
+        let mut pool = InstancePool::new("Test Pool", wgpu::BufferUsages::STORAGE);
+
+        let handle = pool.insert(&state, &numbers(0, 10));
+        let next = pool.insert(&state, &numbers(100, 10));
+
+        assert!(pool.replace(&state, handle, &numbers(50, 4)));
+        assert_eq!(pool.range(handle), Some(0..4));
+        assert_eq!(pool.stats().free_ranges, 2);
+        assert_eq!(read(&state, &pool, handle), numbers(50, 4));
+        assert_eq!(read(&state, &pool, next), numbers(100, 10));
+
+        // longer instances move to the first range they fit in
+        assert!(pool.replace(&state, handle, &numbers(70, 12)));
+        assert_eq!(pool.range(handle), Some(20..32));
+        assert_eq!(read(&state, &pool, handle), numbers(70, 12));
+
+        assert!(pool.remove(handle));
+        assert!(!pool.replace(&state, handle, &numbers(0, 1)));
+    }
+
+    #[test]
+    fn defragment_makes_the_pool_compact() {
+        let state = headless_state();
+        let mut pool = InstancePool::new("Test Pool", wgpu::BufferUsages::STORAGE);
+
+        let handles: Vec<PoolHandle> = (0..8).map(|index| pool.insert(&state, &numbers(index * 100, 10 + index))).collect();
+        for handle in handles.iter().step_by(2) {
+            pool.remove(*handle);
+        }
+        assert!(pool.stats().fragmentation() > 0.0);
+
+        // one move at a time, each range may overlap the space it moved out of
+        let mut moves = 0;
+        while pool.defragment(&state, 1) > 0 {
+            moves += 1;
+        }
+
+        assert_eq!(moves, 4);
+        assert_eq!(pool.stats().fragmentation(), 0.0);
+        assert_eq!(pool.stats().free_ranges, 1);
+        for (index, handle) in handles.iter().enumerate().skip(1).step_by(2) {
+            assert_eq!(read(&state, &pool, *handle), numbers(index as u32 * 100, 10 + index as u32));
+        }
+    }
+}
@@ -7,5 +7,17 @@ pub use renderer::*;
 pub mod set;
 pub use set::*;
 
-pub mod intance_manager;
-pub use intance_manager::*;
+pub mod instance_buffer;
+pub use instance_buffer::*;
+
+pub mod packed_face;
+pub use packed_face::*;
+
+pub mod instance_pool;
+pub use instance_pool::*;
+
+pub mod instance;
+pub use instance::*;
+
+pub mod block_textures;
+pub use block_textures::*;
@@ -0,0 +1,76 @@
+use crate::BlockId;
+
+
+/// A unit sized block face in 32 bits, read by `packed_vert` in res/shader.wgsl.
+/// Bits 0-3, 4-7 and 8-11 are the x, y and z position within the chunk,
+/// bits 12-14 the side, same as `ShiftDirection`, and bits 15-31 the block id.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedFace(pub u32);
+
+impl PackedFace {
+    pub fn position(&self) -> glam::UVec3 {
+        glam::uvec3(self.0 & 15, self.0 >> 4 & 15, self.0 >> 8 & 15)
+    }
+
+    pub fn face(&self) -> u32 {
+        self.0 >> 12 & 7
+    }
+
+    pub fn block_id(&self) -> BlockId {
+        (self.0 >> 15) as BlockId
+    }
+}
+
+impl PackedFace {
+    /// Largest chunk size the position fits into
+    pub const MAX_CHUNK_SIZE: usize = 16;
+
+    /// args:
+    ///  - position: within the chunk, each coordinate less than `MAX_CHUNK_SIZE`
+    ///  - face: index of the side, same as `ShiftDirection`
+    pub fn new(position: glam::UVec3, face: u32, block_id: BlockId) -> Self {
+        debug_assert!(position.cmplt(glam::UVec3::splat(Self::MAX_CHUNK_SIZE as u32)).all(), "{} is out of the chunk", position);
+        debug_assert!(face < 6, "{} is not a side", face);
+
+        Self(position.x | position.y << 4 | position.z << 8 | face << 12 | (block_id as u32) << 15)
+    }
+}
+
+
+/// Where the packed faces of one drawn chunk start in the face buffer and where the chunk lies,
+/// the shader finds it through the vertex index, see `Renderer::draw`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedDraw {
+    /// World position of the block at (0, 0, 0) of the chunk
+    pub origin: [i32; 3],
+    pub first_face: u32,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for position in [glam::UVec3::ZERO, glam::uvec3(15, 0, 7), glam::uvec3(3, 15, 0), glam::UVec3::splat(15)] {
+            for face in 0..6 {
+                for block_id in [0, 1, 300, BlockId::MAX] {
+                    let packed = PackedFace::new(position, face, block_id);
+
+                    assert_eq!(packed.position(), position);
+                    assert_eq!(packed.face(), face);
+                    assert_eq!(packed.block_id(), block_id);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bit_layout() {
+        let packed = PackedFace::new(glam::uvec3(1, 2, 3), 4, 5);
+        assert_eq!(packed.0, 1 | 2 << 4 | 3 << 8 | 4 << 12 | 5 << 15);
+    }
+}
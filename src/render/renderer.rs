@@ -1,8 +1,9 @@
-use wgpu::util::DeviceExt;
+use wgpu::util::{DeviceExt, DrawIndexedIndirect};
 
-use crate::{HardwareState, Shader, RenderSet, Vertex, Descriptable, QUAD_INDICES, QUAD_VERTICES, Texture};
+use crate::{HardwareState, Shader, RenderSet, Vertex, Descriptable, QUAD_INDICES, QUAD_VERTICES, Texture, Instance, InstanceBuffer, ChunkDraws, PackedDraw, TextureReadback};
 
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PipelineType {
     Triangle,
     Line,
@@ -17,6 +18,20 @@ impl PipelineType {
     }
 }
 
+/// A frame copied by `Renderer::capture_next_frame`.
+pub struct CapturedFrame {
+    pub size: winit::dpi::PhysicalSize<u32>,
+    /// RGBA bytes, row by row from the top left
+    pub pixels: Vec<u8>,
+}
+
+/// Pipelines of the packed face format, which pull the faces from storage buffers.
+struct PackedPipelines {
+    render_pipeline: wgpu::RenderPipeline,
+    line_render_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
 pub struct Renderer {
     render_pipeline: wgpu::RenderPipeline,
     line_render_pipeline: wgpu::RenderPipeline,
@@ -33,6 +48,14 @@ pub struct Renderer {
     bind_groups: Vec<wgpu::BindGroup>,
     _sets: Vec<RenderSet>,
     active_pipeline: PipelineType,
+
+    indirect_draws: Option<InstanceBuffer<u8>>, // bytes of `DrawIndexedIndirect`s, None without multi draw indirect support
+
+    packed_pipelines: Option<PackedPipelines>, // None without base vertex support
+    packed_draws: InstanceBuffer<PackedDraw>,
+
+    capture_requested: bool,
+    captures: std::collections::VecDeque<TextureReadback>, // copies of captured frames, oldest first
 }
 
 impl Renderer {
@@ -44,9 +67,9 @@ impl Renderer {
     ) -> wgpu::RenderPipeline {
         let render_pipeline = state.device().create_render_pipeline(&wgpu::RenderPipelineDescriptor{
             label: Some("Render Pipeline"),
-            layout: Some(&layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                buffers: &vertex_layouts,
+                buffers: vertex_layouts,
                 ..pipeline_descriptor.vertex.clone()
             },
             ..pipeline_descriptor.clone()
@@ -62,11 +85,19 @@ impl Renderer {
         pipeline_descriptor: &wgpu::RenderPipelineDescriptor,
         fragment_state: &wgpu::FragmentState,
     ) -> wgpu::RenderPipeline {
+        // software adapters can lack line rendering, the wireframe then only changes the colors
+        let polygon_mode = if state.device().features().contains(wgpu::Features::POLYGON_MODE_LINE) {
+            wgpu::PolygonMode::Line
+        } else {
+            log::warn!("Line polygon mode is not supported, the wireframe is filled");
+            wgpu::PolygonMode::Fill
+        };
+
         let line_render_pipeline = state.device().create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Line Render Pipeline"),
-            layout: Some(&layout),
+            layout: Some(layout),
             vertex: wgpu::VertexState {
-                buffers: &vertex_layouts,
+                buffers: vertex_layouts,
                 ..pipeline_descriptor.vertex.clone()
             },
             fragment: Some(wgpu::FragmentState {
@@ -74,8 +105,8 @@ impl Renderer {
                 ..fragment_state.clone()
             }),
             primitive: wgpu::PrimitiveState {
-                polygon_mode: wgpu::PolygonMode::Line,
-                ..pipeline_descriptor.primitive.clone()
+                polygon_mode,
+                ..pipeline_descriptor.primitive
             },
             ..pipeline_descriptor.clone()
         });
@@ -99,7 +130,7 @@ impl Renderer {
                 module: ui_shader.module(),
                 entry_point: ui_shader.fragment_entry(),
                 targets: &[Some(wgpu::ColorTargetState { 
-                    format: state.surface_format().clone(),
+                    format: *state.surface_format(),
                     blend: Some(wgpu::BlendState {
                         color: wgpu::BlendComponent { 
                             src_factor: wgpu::BlendFactor::SrcAlpha, 
@@ -132,8 +163,7 @@ impl Renderer {
             push_constant_ranges: &[],
         });
 
-        // let vertex_layouts = [Vertex::desc(), QuadInstance::desc()];
-        let vertex_layouts = [Vertex::desc()];
+        let vertex_layouts = [Vertex::desc(), Instance::desc()];
 
         let vertices_buffer = state.device().create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Quad Vertices Buffer"),
@@ -194,7 +224,7 @@ impl Renderer {
             multiview: None,
         };
         
-        let ms_texture = Texture::create_texture(state, state.window().inner_size(), state.surface_format().clone(), sample_count, Some("MS texture"));
+        let ms_texture = Texture::create_texture(state, state.size(), *state.surface_format(), sample_count, Some("MS texture"));
         
         let render_pipeline = Self::create_render_pipeline(state, &layout, &vertex_layouts, &pipeline_descriptor);
 
@@ -202,6 +232,8 @@ impl Renderer {
 
         let ui_render_pipeline = Self::create_ui_pipeline(state, ui_shader, &pipeline_descriptor);
 
+        let packed_pipelines = Self::create_packed_pipelines(state, bind_group_layouts, &pipeline_descriptor, &fragment_state);
+
         Self {
             render_pipeline,
             line_render_pipeline,
@@ -214,7 +246,101 @@ impl Renderer {
             bind_groups,
             _sets: sets,
             active_pipeline: PipelineType::Triangle,
+            indirect_draws: Self::create_indirect_draws(state),
+            packed_pipelines,
+            packed_draws: InstanceBuffer::new("Packed Draw Buffer", wgpu::BufferUsages::STORAGE),
+            capture_requested: false,
+            captures: std::collections::VecDeque::new(),
+        }
+    }
+
+    /// Vertex shader entry of the packed face format
+    const PACKED_VERTEX_ENTRY: &'static str = "packed_vert";
+
+    /// The packed pipelines use the bind groups of the other pipelines
+    /// followed by one with the packed faces and the packed draws.
+    fn create_packed_pipelines(
+        state: &HardwareState,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        pipeline_descriptor: &wgpu::RenderPipelineDescriptor,
+        fragment_state: &wgpu::FragmentState,
+    ) -> Option<PackedPipelines> {
+        // the chunk of each packed face is found through the base vertex
+        let required = wgpu::DownlevelFlags::BASE_VERTEX | wgpu::DownlevelFlags::VERTEX_STORAGE;
+        if !state.adapter().get_downlevel_capabilities().flags.contains(required) {
+            log::info!("Packed faces are not supported, base vertex or storage buffers in vertex shaders are missing");
+            return None;
+        }
+
+        // DX12 leaves the base vertex out of the vertex index, every draw would read the faces of the first chunk
+        if state.adapter().get_info().backend == wgpu::Backend::Dx12 {
+            log::info!("Packed faces are not supported on DX12, the vertex index lacks the base vertex");
+            return None;
+        }
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = state.device().create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Packed Faces Bind Group Layout"),
+            entries: &[storage_entry(0), storage_entry(1)],
+        });
+
+        let layout = state.device().create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Packed Render Pipeline Layout"),
+            bind_group_layouts: &[bind_group_layouts, &[&bind_group_layout]].concat(),
+            push_constant_ranges: &[],
+        });
+
+        let pipeline_descriptor = wgpu::RenderPipelineDescriptor {
+            vertex: wgpu::VertexState {
+                entry_point: Self::PACKED_VERTEX_ENTRY,
+                ..pipeline_descriptor.vertex.clone()
+            },
+            ..pipeline_descriptor.clone()
+        };
+
+        Some(PackedPipelines {
+            render_pipeline: Self::create_render_pipeline(state, &layout, &[], &pipeline_descriptor),
+            line_render_pipeline: Self::create_wireframe_pipeline(state, &layout, &[], &pipeline_descriptor, fragment_state),
+            bind_group_layout,
+        })
+    }
+
+    /// The shader finds the packed draw through the vertex index, offset by the 4 vertices of a quad per draw.
+    fn packed_base_vertex(draw_index: usize) -> i32 {
+        (draw_index * QUAD_VERTICES.len()) as i32
+    }
+
+    /// Whether chunks meshed in `FaceFormat::Packed` can be drawn.
+    pub fn supports_packed_faces(&self) -> bool {
+        self.packed_pipelines.is_some()
+    }
+
+    /// Features needed to draw all meshes with one `multi_draw_indexed_indirect`
+    const INDIRECT_FEATURES: wgpu::Features = wgpu::Features::MULTI_DRAW_INDIRECT
+        .union(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+
+    fn create_indirect_draws(state: &HardwareState) -> Option<InstanceBuffer<u8>> {
+        if !state.device().features().contains(Self::INDIRECT_FEATURES) {
+            log::info!("Multi draw indirect is not supported, drawing every chunk separately");
+            return None;
         }
+
+        Some(InstanceBuffer::new("Indirect Draw Buffer", wgpu::BufferUsages::INDIRECT))
+    }
+
+    /// Whether the meshes are drawn with one indirect draw call, otherwise there is one draw call per mesh.
+    pub fn uses_indirect_draws(&self) -> bool {
+        self.indirect_draws.is_some()
     }
 
     fn get_active_pipeline(&self) -> &wgpu::RenderPipeline {
@@ -224,24 +350,122 @@ impl Renderer {
         }
     }
 
+    fn get_active_packed_pipeline(&self) -> Option<&wgpu::RenderPipeline> {
+        let pipelines = self.packed_pipelines.as_ref()?;
+
+        Some(match self.active_pipeline {
+            PipelineType::Triangle => &pipelines.render_pipeline,
+            PipelineType::Line     => &pipelines.line_render_pipeline,
+        })
+    }
+
     pub fn toggle_pipeline(&mut self) {
         self.active_pipeline.toggle();
     }
 
-    pub fn render(&self, state: &HardwareState) -> Result<(), wgpu::SurfaceError> {
+    pub fn pipeline_type(&self) -> PipelineType {
+        self.active_pipeline
+    }
+
+    pub fn set_pipeline_type(&mut self, pipeline_type: PipelineType) {
+        self.active_pipeline = pipeline_type;
+    }
+
+    /// args:
+    ///  - meshes: the chunks to draw, packed ones are skipped without `supports_packed_faces`
+    pub fn render(&mut self, state: &HardwareState, meshes: &ChunkDraws) -> Result<(), wgpu::SurfaceError> {
+        let frame = state.current_frame()?;
+        self.draw(state, frame.view(), meshes);
+
+        if std::mem::take(&mut self.capture_requested) {
+            self.captures.push_back(TextureReadback::copy(state, frame.texture()));
+        }
+        frame.present();
+
+        Ok(())
+    }
+
+    /// Copies the next rendered frame, as it is presented, into a readback buffer.
+    /// The pixels are returned by `take_captured_frame` once the GPU is done copying them.
+    /// Returns false without capturing if the frames can not be copied, see `HardwareState::supports_frame_capture`.
+    pub fn capture_next_frame(&mut self, state: &HardwareState) -> bool {
+        self.capture_requested = state.supports_frame_capture();
+        self.capture_requested
+    }
+
+    /// Returns the oldest captured frame, never waits for the GPU and returns None until the copy is done.
+    pub fn take_captured_frame(&mut self, state: &HardwareState) -> Option<Result<CapturedFrame, wgpu::BufferAsyncError>> {
+        let pixels = self.captures.front()?.try_read(state)?;
+        let capture = self.captures.pop_front()?;
+
+        Some(pixels.map(|pixels| CapturedFrame { size: capture.size(), pixels }))
+    }
+
+    /// Uploads the packed draws and returns the bind group with them, None if there are no packed chunks to draw.
+    fn prepare_packed_draws(&mut self, state: &HardwareState, meshes: &ChunkDraws) -> Option<wgpu::BindGroup> {
+        let pipelines = self.packed_pipelines.as_ref()?;
+        if meshes.packed.is_empty() {
+            return None;
+        }
+
+        self.packed_draws.set(meshes.packed.iter().map(|(origin, range)| PackedDraw {
+            origin: origin.to_array(),
+            first_face: range.start,
+        }).collect());
+        self.packed_draws.upload(state);
+
+        // the pool buffer is replaced when it grows, so the bind group is created every frame
+        Some(state.device().create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Packed Faces Bind Group"),
+            layout: &pipelines.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: meshes.pools.packed().buffer()?.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.packed_draws.buffer()?.as_entire_binding(),
+                },
+            ],
+        }))
+    }
+
+    fn draw(&mut self, state: &HardwareState, view: &wgpu::TextureView, meshes: &ChunkDraws) {
+        let packed_bind_group = self.prepare_packed_draws(state, meshes);
+
+        // the packed draws follow the instanced ones
+        if let Some(indirect_draws) = &mut self.indirect_draws {
+            let instanced = meshes.instanced.iter().map(|range| DrawIndexedIndirect {
+                vertex_count: QUAD_INDICES.len() as u32,
+                instance_count: range.end - range.start,
+                base_index: 0,
+                vertex_offset: 0,
+                base_instance: range.start,
+            });
+            let packed = meshes.packed.iter().enumerate().map(|(index, (_, range))| DrawIndexedIndirect {
+                vertex_count: QUAD_INDICES.len() as u32,
+                instance_count: range.end - range.start,
+                base_index: 0,
+                vertex_offset: Self::packed_base_vertex(index),
+                base_instance: 0,
+            });
+
+            let draws: Vec<DrawIndexedIndirect> = instanced.chain(packed).collect();
+            indirect_draws.set(draws.iter().flat_map(DrawIndexedIndirect::as_bytes).copied().collect());
+            indirect_draws.upload(state);
+        }
+
         let mut encoder = state.device().create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
-        
-        let texture = state.surface().get_current_texture()?;
-        let view = texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor { 
                 label: Some("Render Pass"), 
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment { 
-                    view: if self.sample_count == 1 { &view } else { self.ms_texture.view() }, 
-                    resolve_target: if self.sample_count == 1 { None } else { Some(&view) }, 
+                    view: if self.sample_count == 1 { view } else { self.ms_texture.view() }, 
+                    resolve_target: if self.sample_count == 1 { None } else { Some(view) }, 
                     ops: wgpu::Operations { 
                         load: wgpu::LoadOp::Clear(
                             wgpu::Color { 
@@ -267,18 +491,46 @@ impl Renderer {
             // block rendering
             render_pass.set_pipeline(self.get_active_pipeline());
             
-            for bind_group in self.bind_groups.iter() {
-                render_pass.set_bind_group(0, bind_group, &[]);
+            for (index, bind_group) in self.bind_groups.iter().enumerate() {
+                render_pass.set_bind_group(index as u32, bind_group, &[]);
             }
 
             render_pass.set_vertex_buffer(0, self.vertices_buffer.slice(..));
             render_pass.set_index_buffer(self.indices_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
-            // for (i, render_set) in self.sets.iter().enumerate() {
-            //     render_pass.set_vertex_buffer(i + 1, render_set.instances_buffer().slice(..));
-            // }
+            let indirect_buffer = self.indirect_draws.as_ref().and_then(|draws| draws.buffer());
+
+            if let (Some(buffer), false) = (meshes.pools.instanced().buffer(), meshes.instanced.is_empty()) {
+                render_pass.set_vertex_buffer(1, buffer.slice(..));
+
+                match indirect_buffer {
+                    Some(indirect_buffer) => {
+                        render_pass.multi_draw_indexed_indirect(indirect_buffer, 0, meshes.instanced.len() as u32);
+                    },
+                    None => {
+                        for range in &meshes.instanced {
+                            render_pass.draw_indexed(0..QUAD_INDICES.len() as _, 0, range.clone());
+                        }
+                    },
+                }
+            }
 
-            render_pass.draw_indexed(0..QUAD_INDICES.len() as _, 0, 0..6000);
+            if let (Some(pipeline), Some(bind_group)) = (self.get_active_packed_pipeline(), &packed_bind_group) {
+                render_pass.set_pipeline(pipeline);
+                render_pass.set_bind_group(self.bind_groups.len() as u32, bind_group, &[]);
+
+                match indirect_buffer {
+                    Some(indirect_buffer) => {
+                        let offset = (meshes.instanced.len() * std::mem::size_of::<DrawIndexedIndirect>()) as wgpu::BufferAddress;
+                        render_pass.multi_draw_indexed_indirect(indirect_buffer, offset, meshes.packed.len() as u32);
+                    },
+                    None => {
+                        for (index, (_, range)) in meshes.packed.iter().enumerate() {
+                            render_pass.draw_indexed(0..QUAD_INDICES.len() as _, Self::packed_base_vertex(index), 0..range.end - range.start);
+                        }
+                    },
+                }
+            }
 
             // UI rendering
             render_pass.set_pipeline(&self.ui_render_pipeline);
@@ -290,9 +542,6 @@ impl Renderer {
         }
 
         state.queue().submit(std::iter::once(encoder.finish()));
-        texture.present();
-
-        Ok(())
     }
 
     pub fn resize(&mut self, state: &HardwareState, size: winit::dpi::PhysicalSize<u32>) {
@@ -1,8 +1,8 @@
 use std::rc::Rc;
 
-use wgpu::util::DeviceExt;
+use bytemuck::Zeroable;
 
-use crate::HardwareState;
+use crate::{HardwareState, Instance, InstanceBuffer};
 
 
 
@@ -22,140 +22,82 @@ pub trait Descriptable {
 }
 
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct QuadInstance { }
-
-impl Descriptable for QuadInstance {
-    const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::Instance;
-    const SIZE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
-
-    fn attribs() -> &'static [wgpu::VertexAttribute] {
-        &wgpu::vertex_attr_array![]
-    }
-}
+pub type QuadInstance = Instance;
 
 
 pub struct RenderSet {
-    instances: Vec<QuadInstance>,
+    instances: InstanceBuffer<QuadInstance>,
     instances_queue: Vec<QuadInstance>,
-    instances_size: usize,
-    
-    state_ref: Rc<HardwareState>,
 
-    instances_buffer: wgpu::Buffer,
+    state_ref: Rc<HardwareState>,
 }
 
 impl RenderSet {
     pub fn instances(&self) -> &[QuadInstance] {
-        &self.instances
+        self.instances.instances()
     }
 
     pub fn instances_len(&self) -> usize {
         self.instances.len()
     }
 
-    pub fn instances_buffer(&self) -> &wgpu::Buffer {
-        &self.instances_buffer
+    pub fn instances_buffer(&self) -> Option<&wgpu::Buffer> {
+        self.instances.buffer()
     }
 }
 
 impl RenderSet {
-    const INSTANCES_DESCRIPTOR: wgpu::util::BufferInitDescriptor<'static> = wgpu::util::BufferInitDescriptor {
-        label: Some("RenderSet Instance Buffer"),
-        contents: &[],
-        usage: wgpu::BufferUsages::VERTEX,
-    };
-
     pub fn new(
         instances: Vec<QuadInstance>,
         state_ref: Rc<HardwareState>,
     ) -> Self {
-        let instances_buffer = state_ref.device().create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                contents: bytemuck::cast_slice(&instances),
-                ..Self::INSTANCES_DESCRIPTOR
-            }
-        );
-
-        let instances_queue = Vec::new();
-        let instances_size = instances.len();
-
-        Self { 
-            instances,
-            instances_queue,
-            instances_size,
-            state_ref: state_ref.clone(),
-            instances_buffer,
+        let mut instance_buffer = InstanceBuffer::new("RenderSet Instance Buffer", wgpu::BufferUsages::VERTEX);
+        instance_buffer.set(instances);
+        instance_buffer.upload(&state_ref);
+
+        Self {
+            instances: instance_buffer,
+            instances_queue: Vec::new(),
+            state_ref,
         }
     }
 
-    pub fn set_instances(&mut self, instances: Vec<QuadInstance>) -> Result<(), &str> {
-        if instances.len() > self.instances_size {
-            return Err("New instances length does not match old instances length");
-        }
-
-        self.instances = instances;
-        self.state_ref.queue().write_buffer(
-            &self.instances_buffer,
-            0,
-            bytemuck::cast_slice(&self.instances),
-        );
-
-        Ok(())
+    /// The buffer grows when the instances do not fit into it.
+    pub fn set_instances(&mut self, instances: Vec<QuadInstance>) {
+        self.instances.set(instances);
+        self.instances.upload(&self.state_ref);
     }
 
+    /// The last instance takes the place of the removed one.
     pub fn remove_instance(&mut self, index: usize) {
-        self.instances.remove(index);
+        self.instances.swap_remove(index);
     }
 
     pub fn add_instance(&mut self) {
-        let instance = QuadInstance {};
+        let instance = QuadInstance::zeroed();
         self.instances_queue.push(instance);
     }
 
     pub fn add_instances(&mut self, number: usize) {
-        let mut instances = vec![QuadInstance {}; number];
+        let mut instances = vec![QuadInstance::zeroed(); number];
         self.instances_queue.append(&mut instances);
     }
 
-    /// Instances may be removed from the memory at any time,
-    /// this syncs the buffer with the memory
-    pub fn update_instances(&mut self) -> Result<(), &str> {
-        if self.instances.len() > self.instances_size {
-            return Err("New instances length does not match old instances length");
-        }
-
-        self.state_ref.queue().write_buffer(
-            &self.instances_buffer,
-            0,
-            bytemuck::cast_slice(&self.instances),
-        ); 
-
-        Ok(())
+    /// Writes the instances changed since the last update into the buffer.
+    pub fn update_instances(&mut self) {
+        self.instances.upload(&self.state_ref);
     }
 
-    /// It is intended to run only when necessary, 
-    /// because it is creating a whole new buffer 
+    /// Appends the queued instances and uploads only them,
+    /// together with any other change since the last update.
     pub fn submit_instances(&mut self) -> Result<(), &str> {
         if self.instances_queue.is_empty() {
             return Err("No instances to submit");
         }
 
-        // update the instances
-        let new_instances = &mut self.instances;
-        new_instances.append(&mut self.instances_queue);
-
-        self.instances_size = new_instances.len();
-
-        // swap the buffers
-        self.instances_buffer.destroy();
-        self.instances_buffer = self.state_ref.device().create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                contents: bytemuck::cast_slice(&new_instances),
-                ..Self::INSTANCES_DESCRIPTOR
-            }
-        );
+        self.instances.extend(&self.instances_queue);
+        self.instances_queue.clear();
+        self.instances.upload(&self.state_ref);
 
         Ok(())
     }
@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+
+/// Writes RGBA bytes, row by row from the top left, into a PNG file.
+pub fn save_png(path: impl AsRef<Path>, size: winit::dpi::PhysicalSize<u32>, pixels: &[u8]) -> Result<(), png::EncodingError> {
+    if let Some(directory) = path.as_ref().parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, size.width, size.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels)?;
+
+    Ok(())
+}
+
+/// Path of a new screenshot inside of the directory,
+/// named after the current UTC time, e.g. screenshot_2024-01-31_12-30-05_123.png
+pub fn screenshot_path(directory: impl AsRef<Path>) -> PathBuf {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    directory.as_ref().join(screenshot_name(since_epoch))
+}
+
+/// File name of a screenshot taken at the given time since 1970-01-01 UTC.
+fn screenshot_name(since_epoch: std::time::Duration) -> String {
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let time = seconds % 86400;
+
+    format!(
+        "screenshot_{:04}-{:02}-{:02}_{:02}-{:02}-{:02}_{:03}.png",
+        year, month, day, time / 3600, time / 60 % 60, time % 60, since_epoch.subsec_millis(),
+    )
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date.
+/// Howard Hinnant's algorithm: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(20_088), (2024, 12, 31));
+        assert_eq!(civil_from_days(20_089), (2025, 1, 1));
+    }
+
+    #[test]
+    fn name_of_a_known_time() {
+        // 2024-12-31 23:59:58.007 UTC
+        let since_epoch = std::time::Duration::from_millis(20_088 * 86_400_000 + 86_398_007);
+
+        assert_eq!(screenshot_name(since_epoch), "screenshot_2024-12-31_23-59-58_007.png");
+        assert_eq!(screenshot_name(std::time::Duration::ZERO), "screenshot_1970-01-01_00-00-00_000.png");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{Chunk, BlockId, fractal_noise_2d};
+
+/// Fills newly loaded chunks with blocks.
+/// The same chunk position and seed must always produce the same blocks.
+pub trait TerrainGenerator {
+    fn generate(&self, chunk: &mut Chunk, seed: u64);
+}
+
+
+/// Fills everything below the given height with one block.
+pub struct FlatGenerator {
+    pub height: i32,
+    pub block: BlockId,
+}
+
+impl TerrainGenerator for FlatGenerator {
+    fn generate(&self, chunk: &mut Chunk, _seed: u64) {
+        let origin = chunk.origin();
+
+        for y in 0..Chunk::SIZE {
+            if origin.y + y as i32 >= self.height {
+                break;
+            }
+
+            for z in 0..Chunk::SIZE {
+                for x in 0..Chunk::SIZE {
+                    chunk.set_block(x, y, z, self.block);
+                }
+            }
+        }
+    }
+}
+
+
+/// Rolling hills from a heightmap of fractal gradient noise.
+pub struct NoiseHeightmapGenerator {
+    /// Number of noise layers, each adds finer detail
+    pub octaves: u32,
+    /// Frequency of the first octave, in 1 / blocks
+    pub frequency: f32,
+    /// Height of the hills above and valleys below the base height, in blocks
+    pub amplitude: f32,
+    /// Frequency multiplier between octaves
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves
+    pub persistence: f32,
+    pub base_height: i32,
+
+    pub surface_block: BlockId,
+    pub subsurface_block: BlockId,
+    pub subsurface_depth: i32,
+    pub stone_block: BlockId,
+}
+
+impl NoiseHeightmapGenerator {
+    /// Height of the terrain surface at the world column, the topmost solid block is at height - 1.
+    pub fn height_at(&self, x: i32, z: i32, seed: u64) -> i32 {
+        let noise = fractal_noise_2d(x as f32, z as f32, seed, self.octaves, self.frequency, self.lacunarity, self.persistence);
+        self.base_height + (noise * self.amplitude).round() as i32
+    }
+}
+
+impl Default for NoiseHeightmapGenerator {
+    fn default() -> Self {
+        Self {
+            octaves: 4,
+            frequency: 0.01,
+            amplitude: 16.0,
+            lacunarity: 2.0,
+            persistence: 0.5,
+            base_height: 0,
+
+            surface_block: 3,
+            subsurface_block: 2,
+            subsurface_depth: 3,
+            stone_block: 1,
+        }
+    }
+}
+
+impl TerrainGenerator for NoiseHeightmapGenerator {
+    fn generate(&self, chunk: &mut Chunk, seed: u64) {
+        let origin = chunk.origin();
+
+        for z in 0..Chunk::SIZE {
+            for x in 0..Chunk::SIZE {
+                let height = self.height_at(origin.x + x as i32, origin.z + z as i32, seed);
+
+                for y in 0..Chunk::SIZE {
+                    let world_y = origin.y + y as i32;
+                    let depth = height - 1 - world_y;
+
+                    let block = if depth < 0 {
+                        break;
+                    } else if depth == 0 {
+                        self.surface_block
+                    } else if depth <= self.subsurface_depth {
+                        self.subsurface_block
+                    } else {
+                        self.stone_block
+                    };
+
+                    chunk.set_block(x, y, z, block);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u64 = 42;
+
+    fn generate(position: glam::IVec3, seed: u64) -> Chunk {
+        let mut chunk = Chunk::new(position);
+        NoiseHeightmapGenerator::default().generate(&mut chunk, seed);
+
+        chunk
+    }
+
+    #[test]
+    fn same_seed_and_position_give_the_same_blocks() {
+        for position in [glam::ivec3(0, -1, 0), glam::ivec3(-3, 0, 5), glam::ivec3(7, -2, -4)] {
+            assert_eq!(generate(position, SEED).blocks(), generate(position, SEED).blocks(), "chunk {}", position);
+        }
+
+        assert_ne!(generate(glam::ivec3(0, -1, 0), SEED).blocks(), generate(glam::ivec3(0, -1, 0), SEED + 1).blocks());
+    }
+
+    /// Changes to the noise or the generator show up here, update the snapshot only for intended changes.
+    #[test]
+    fn heightmap_snapshot() {
+        let generator = NoiseHeightmapGenerator::default();
+        let heights: Vec<Vec<i32>> = (0..4)
+            .map(|z| (0..4).map(|x| generator.height_at(x * 37 - 50, z * 37 - 50, SEED)).collect())
+            .collect();
+
+        assert_eq!(heights, [
+            [-14, -11, -9, 2],
+            [-8, -3, 1, 0],
+            [-1, 12, 10, 5],
+            [7, 7, 6, 5],
+        ]);
+    }
+
+    #[test]
+    fn chunk_snapshot() {
+        let chunk = generate(glam::ivec3(0, -1, 0), SEED);
+
+        let mut counts = std::collections::BTreeMap::new();
+        for z in 0..Chunk::SIZE {
+            for y in 0..Chunk::SIZE {
+                for x in 0..Chunk::SIZE {
+                    *counts.entry(chunk.get_block(x, y, z)).or_insert(0) += 1;
+                }
+            }
+        }
+
+        assert_eq!(counts.into_iter().collect::<Vec<(BlockId, usize)>>(), [(0, 16), (1, 3541), (2, 458), (3, 81)]);
+    }
+}
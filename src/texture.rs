@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use crate::HardwareState;
 
 
@@ -15,6 +17,13 @@ pub fn create_texture(
         depth_or_array_layers: 1,
     };
 
+    // multisampled attachments are never sampled, GL can only resolve them without the binding usage
+    let usage = if sample_count == 1 {
+        wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+    } else {
+        wgpu::TextureUsages::RENDER_ATTACHMENT
+    };
+
     let desc = wgpu::TextureDescriptor {
         label,
         size,
@@ -22,7 +31,7 @@ pub fn create_texture(
         sample_count,
         dimension: wgpu::TextureDimension::D2,
         format,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        usage,
         view_formats: &[format],
     }; 
 
@@ -32,6 +41,186 @@ pub fn create_texture(
     (texture, view)
 }
 
+/// A copy of a 4 byte per pixel texture on its way into a mappable buffer.
+/// Reading it with `try_read` never waits for the GPU, so it can be polled once per frame.
+pub struct TextureReadback {
+    buffer: wgpu::Buffer,
+    size: winit::dpi::PhysicalSize<u32>,
+    format: wgpu::TextureFormat,
+    padded_bytes_per_row: u32,
+    mapped: std::sync::mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl TextureReadback {
+    /// Size of the copied texture in pixels.
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.size
+    }
+}
+
+impl TextureReadback {
+    const BYTES_PER_PIXEL: u32 = 4;
+
+    /// Submits the copy of the texture after everything submitted before, so it sees the finished frame.
+    /// The texture needs the COPY_SRC usage.
+    pub fn copy(state: &HardwareState, texture: &wgpu::Texture) -> Self {
+        let size = texture.size();
+        let unpadded_bytes_per_row = size.width * Self::BYTES_PER_PIXEL;
+        // rows of the copy have to be aligned
+        let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(alignment) * alignment;
+
+        let buffer = state.device().create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Texture Readback Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = state.device().create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Texture Readback Encoder"),
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            wgpu::Extent3d {
+                depth_or_array_layers: 1,
+                ..size
+            },
+        );
+
+        state.queue().submit(std::iter::once(encoder.finish()));
+
+        // the callback runs once the copy is done, during one of the following device polls
+        let (sender, mapped) = std::sync::mpsc::channel();
+        buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        Self {
+            buffer,
+            size: winit::dpi::PhysicalSize::new(size.width, size.height),
+            format: texture.format(),
+            padded_bytes_per_row,
+            mapped,
+        }
+    }
+
+    /// Returns the RGBA bytes, row by row from the top left, or None if the GPU is not done yet.
+    /// BGRA textures get their channels swapped.
+    pub fn try_read(&self, state: &HardwareState) -> Option<Result<Vec<u8>, wgpu::BufferAsyncError>> {
+        state.device().poll(wgpu::Maintain::Poll);
+
+        match self.mapped.try_recv() {
+            Ok(result) => Some(result.map(|_| self.pixels())),
+            Err(std::sync::mpsc::TryRecvError::Empty) => None,
+            // the callback was dropped without running
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => Some(Err(wgpu::BufferAsyncError)),
+        }
+    }
+
+    /// Blocks until the GPU is done, see `try_read`.
+    pub fn wait(&self, state: &HardwareState) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+        state.device().poll(wgpu::Maintain::Wait);
+
+        match self.mapped.recv() {
+            Ok(result) => result.map(|_| self.pixels()),
+            Err(_) => Err(wgpu::BufferAsyncError),
+        }
+    }
+
+    /// Reads the mapped buffer without the row padding.
+    fn pixels(&self) -> Vec<u8> {
+        let unpadded_bytes_per_row = (self.size.width * Self::BYTES_PER_PIXEL) as usize;
+        let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.size.height as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+            }
+        }
+        self.buffer.unmap();
+
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_mut(Self::BYTES_PER_PIXEL as usize) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        pixels
+    }
+}
+
+/// Copies a 4 byte per pixel texture into RGBA bytes, row by row from the top left, and waits for it.
+/// The texture needs the COPY_SRC usage, BGRA textures get their channels swapped.
+/// Returns an error if the readback buffer could not be mapped.
+pub fn read_texture_rgba(state: &HardwareState, texture: &wgpu::Texture) -> Result<Vec<u8>, wgpu::BufferAsyncError> {
+    TextureReadback::copy(state, texture).wait(state)
+}
+
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(std::io::Error),
+    Decoding(png::DecodingError),
+    Unsupported(String),
+}
+
+impl std::fmt::Display for TextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "Texture io error: {}", error),
+            Self::Decoding(error) => write!(f, "Texture decoding error: {}", error),
+            Self::Unsupported(message) => write!(f, "Unsupported texture: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<std::io::Error> for TextureError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(error: png::DecodingError) -> Self {
+        Self::Decoding(error)
+    }
+}
+
+/// Loads a PNG image as RGBA bytes, row by row from the top left.
+pub fn load_png(path: impl AsRef<Path>) -> Result<(winit::dpi::PhysicalSize<u32>, Vec<u8>), TextureError> {
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(std::fs::File::open(path)?));
+    // palettes and 16 bit channels become 8 bit RGB(A) or grayscale
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => data,
+        png::ColorType::Rgb => data.chunks(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => data.chunks(2).flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]]).collect(),
+        png::ColorType::Grayscale => data.iter().flat_map(|gray| [*gray, *gray, *gray, 255]).collect(),
+        color_type => return Err(TextureError::Unsupported(format!("color type {:?}", color_type))),
+    };
+
+    Ok((winit::dpi::PhysicalSize::new(info.width, info.height), pixels))
+}
+
 
 
 pub struct Texture {
@@ -62,7 +251,7 @@ impl Texture {
 
 
     pub fn create_depth_texture(state: &HardwareState, sample_count: u32) -> Self {
-        let (texture, view) = create_texture(state, state.window().inner_size(), Self::DEPTH_FORMAT, sample_count, Some("Depth Texture"));
+        let (texture, view) = create_texture(state, state.size(), Self::DEPTH_FORMAT, sample_count, Some("Depth Texture"));
 
         let sampler = state.device().create_sampler(&wgpu::SamplerDescriptor {
             label: Some("Depth Texture Sampler"),
@@ -101,6 +290,72 @@ impl Texture {
         }
     }
 
+    /// Creates a 2D array texture with one RGBA layer per image, repeating in both directions.
+    /// args:
+    ///  - layers: RGBA bytes of each layer, all of the given size
+    pub fn create_texture_array(state: &HardwareState, size: winit::dpi::PhysicalSize<u32>, layers: &[Vec<u8>], label: Option<&str>) -> Self {
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let extent = wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: layers.len() as u32,
+        };
+
+        let texture = state.device().create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (index, layer) in layers.iter().enumerate() {
+            state.queue().write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: index as u32 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                layer,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size.width * 4),
+                    rows_per_image: Some(size.height),
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..extent
+                },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = state.device().create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture Array Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            texture,
+            view,
+            sampler,
+            format,
+            sample_count: 1,
+        }
+    }
+
     pub fn resize_texture(&mut self, state: &HardwareState, size: winit::dpi::PhysicalSize<u32>) {
         self.texture.destroy();
         
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{ChunkMap, Chunk, HardwareState, ChunkDraws, BlockId, BlockRegistry, TerrainGenerator, Region, RegionStorage, RegionError, RaycastHit, Aabb, Frustum};
+
+/// Keeps the chunks within the render distance around the camera loaded.
+pub struct World {
+    chunks: ChunkMap,
+    render_distance: u32, // in chunks
+    center: Option<glam::IVec3>, // chunk the loaded area was last centered on
+    seed: u64,
+    generator: Box<dyn TerrainGenerator>,
+    storage: Option<RegionStorage>,
+    unsaved: HashMap<glam::IVec3, Chunk>, // modified chunks that were unloaded, kept until saving them succeeds
+}
+
+impl World {
+    pub fn chunks(&self) -> &ChunkMap {
+        &self.chunks
+    }
+
+    pub fn chunks_mut(&mut self) -> &mut ChunkMap {
+        &mut self.chunks
+    }
+
+    pub fn registry(&self) -> &Arc<BlockRegistry> {
+        self.chunks.registry()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn render_distance(&self) -> u32 {
+        self.render_distance
+    }
+
+    pub fn storage(&self) -> Option<&RegionStorage> {
+        self.storage.as_ref()
+    }
+
+    /// Number of unloaded chunks that could not be saved yet, saving them is retried whenever the loaded area moves and on `save`.
+    pub fn unsaved_chunks(&self) -> usize {
+        self.unsaved.len()
+    }
+
+    /// Modified chunks are saved into the storage when unloaded and 
+    /// loaded back from it instead of being generated again.
+    pub fn set_storage(&mut self, storage: RegionStorage) {
+        self.storage = Some(storage);
+    }
+
+    /// The loaded area is adjusted on the next `update`.
+    pub fn set_render_distance(&mut self, render_distance: u32) {
+        self.render_distance = render_distance;
+        self.center = None;
+    }
+}
+
+impl World {
+    /// args:
+    ///  - render_distance: how many chunks are kept loaded in each direction from the camera
+    ///  - seed: passed to the generator, the same seed always creates the same terrain
+    ///  - generator: fills the chunks when they get loaded
+    ///  - registry: the properties of the blocks the chunks are made of
+    pub fn new(render_distance: u32, seed: u64, generator: impl TerrainGenerator + 'static, registry: Arc<BlockRegistry>) -> Self {
+        Self {
+            chunks: ChunkMap::new(registry),
+            render_distance,
+            center: None,
+            seed,
+            generator: Box::new(generator),
+            storage: None,
+            unsaved: HashMap::new(),
+        }
+    }
+
+    /// Creates the chunk at the given position with the terrain generator.
+    pub fn generate_chunk(&self, position: glam::IVec3) -> Chunk {
+        let mut chunk = Chunk::new(position);
+        self.generator.generate(&mut chunk, self.seed);
+        chunk.mark_saved();
+
+        chunk
+    }
+
+    /// Loads the chunk from the storage if it was saved, otherwise generates it.
+    pub fn load_chunk(&self, position: glam::IVec3) -> Chunk {
+        if let Some(storage) = &self.storage {
+            match storage.load_chunk(position) {
+                Ok(Some(chunk)) => return chunk,
+                Ok(None) => (),
+                Err(error) => log::warn!("Unable to load chunk {}: {}", position, error),
+            }
+        }
+
+        self.generate_chunk(position)
+    }
+
+    /// Same as `load_chunk` for every position, but reads each region file only once.
+    pub fn load_chunks(&self, positions: impl IntoIterator<Item = glam::IVec3>) -> Vec<Chunk> {
+        let mut regions: HashMap<glam::IVec3, Vec<glam::IVec3>> = HashMap::new();
+        for position in positions {
+            let (region_position, _) = Region::split_position(position);
+            regions.entry(region_position).or_default().push(position);
+        }
+
+        let mut chunks = Vec::new();
+        for (region_position, positions) in regions {
+            let region = match &self.storage {
+                Some(storage) => storage.load_region(region_position).unwrap_or_else(|error| {
+                    log::warn!("Unable to load region {}: {}", region_position, error);
+                    None
+                }),
+                None => None,
+            };
+
+            for position in positions {
+                let chunk = match region.as_ref().map(|region| region.get_chunk(position)) {
+                    Some(Ok(Some(chunk))) => chunk,
+                    Some(Ok(None)) | None => self.generate_chunk(position),
+                    Some(Err(error)) => {
+                        log::warn!("Unable to load chunk {}: {}", position, error);
+                        self.generate_chunk(position)
+                    },
+                };
+
+                chunks.push(chunk);
+            }
+        }
+
+        chunks
+    }
+
+    /// Saves every loaded chunk that was modified and the unloaded ones that could not be saved before.
+    pub fn save(&mut self) -> Result<(), RegionError> {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => return Ok(()),
+        };
+
+        let modified: Vec<glam::IVec3> = self.chunks.chunks()
+            .filter(|chunk| chunk.is_modified())
+            .map(|chunk| chunk.position())
+            .collect();
+
+        let loaded = modified.iter().filter_map(|position| self.chunks.get(*position));
+        storage.save_chunks(loaded.chain(self.unsaved.values()))?;
+        self.unsaved.clear();
+
+        for position in modified {
+            if let Some(chunk) = self.chunks.get_mut(position) {
+                chunk.mark_saved();
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_in_range(&self, center: glam::IVec3, position: glam::IVec3) -> bool {
+        (position - center).abs().max_element() <= self.render_distance as i32
+    }
+
+    /// Loads the chunks that came into the render distance, drops those
+    /// that left it and re-meshes the chunks that changed.
+    pub fn update(&mut self, state: &HardwareState, camera_translation: glam::Vec3) {
+        let (center, _) = Chunk::split_position(camera_translation.floor().as_ivec3());
+
+        if self.center != Some(center) {
+            let far_chunks: Vec<glam::IVec3> = self.chunks.positions()
+                .filter(|position| !self.is_in_range(center, **position))
+                .copied()
+                .collect();
+
+            for position in far_chunks {
+                if let Some(chunk) = self.chunks.remove(position) {
+                    if chunk.is_modified() && self.storage.is_some() {
+                        self.unsaved.insert(position, chunk);
+                    }
+                }
+            }
+
+            self.save_unloaded();
+
+            let distance = self.render_distance as i32;
+            let mut missing = Vec::new();
+            for z in -distance..=distance {
+                for y in -distance..=distance {
+                    for x in -distance..=distance {
+                        let position = center + glam::ivec3(x, y, z);
+
+                        // unsaved chunks come back as they were left
+                        if let Some(chunk) = self.unsaved.remove(&position) {
+                            self.chunks.insert(chunk);
+                        } else if !self.chunks.contains(position) {
+                            missing.push(position);
+                        }
+                    }
+                }
+            }
+
+            for chunk in self.load_chunks(missing) {
+                self.chunks.insert(chunk);
+            }
+
+            self.center = Some(center);
+        }
+
+        self.update_meshes(state);
+    }
+
+    /// Saves the modified chunks that were unloaded, they are kept for the next try if it fails.
+    fn save_unloaded(&mut self) {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => return,
+        };
+
+        if self.unsaved.is_empty() {
+            return;
+        }
+
+        match storage.save_chunks(self.unsaved.values()) {
+            Ok(_) => self.unsaved.clear(),
+            Err(error) => log::error!("Unable to save {} unloaded chunks, retrying later: {}", self.unsaved.len(), error),
+        }
+    }
+
+    /// Meshes the chunks with edited blocks, sends the other changed chunks to the mesh workers
+    /// and uploads the finished meshes, without loading or unloading any.
+    pub fn update_meshes(&mut self, state: &HardwareState) {
+        self.chunks.update(state);
+    }
+
+    /// Returns the meshes of the loaded chunks inside of the frustum
+    /// and how many chunks were culled, see `ChunkMap::visible_meshes`.
+    pub fn visible_meshes(&self, frustum: &Frustum) -> (ChunkDraws<'_>, usize) {
+        self.chunks.visible_meshes(frustum)
+    }
+
+    pub fn get_block(&self, world_position: glam::IVec3) -> BlockId {
+        self.chunks.get_block(world_position)
+    }
+
+    pub fn is_solid(&self, world_position: glam::IVec3) -> bool {
+        self.chunks.is_solid(world_position)
+    }
+
+    pub fn collision_box(&self, world_position: glam::IVec3) -> Option<Aabb> {
+        self.chunks.collision_box(world_position)
+    }
+
+    pub fn raycast(&self, origin: glam::Vec3, direction: glam::Vec3, max_distance: f32) -> Option<RaycastHit> {
+        self.chunks.raycast(origin, direction, max_distance)
+    }
+
+    /// Returns false if the block is not in a loaded chunk.
+    pub fn set_block(&mut self, world_position: glam::IVec3, block: BlockId) -> bool {
+        self.chunks.set_block(world_position, block)
+    }
+}
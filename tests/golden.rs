@@ -0,0 +1,236 @@
+//! Renders fixed scenes offscreen and compares them with the reference images in tests/golden.
+//! Run with GOLDEN_BLESS=1 to write new references after an intended visual change,
+//! without it a missing reference fails the test.
+//! On a mismatch the rendered image and a diff are written next to the test binaries.
+//! The tests need an adapter, a software one such as llvmpipe is enough.
+
+use std::path::{Path, PathBuf};
+
+use ::renderer::*;
+
+
+const WIDTH: u32 = 128;
+const HEIGHT: u32 = 96;
+const SAMPLE_COUNT: u32 = 4;
+/// Largest allowed difference of one color channel
+const TOLERANCE: u8 = 3;
+
+
+struct Scene {
+    blocks: Array3D,
+    /// Placed at every set bit of the blocks
+    block: BlockId,
+    camera_position: glam::Vec3,
+    camera_target: glam::Vec3,
+    pipeline_type: PipelineType,
+    face_format: FaceFormat,
+}
+
+/// Panics if there is no adapter to render with or it can not draw the scene.
+fn render(scene: &Scene) -> Vec<u8> {
+    let state = pollster::block_on(HardwareState::new_headless(winit::dpi::PhysicalSize::new(WIDTH, HEIGHT)))
+        .expect("No adapter to render the golden tests with");
+
+    let shader = Shader::new(&state, "res/shader.wgsl", "vert", "frag", Some("shader module"));
+    let ui_shader = Shader::new(&state, "res/ui_shader.wgsl", "vert", "frag", Some("UI shader module"));
+
+    let mut camera = Camera::new(&state, scene.camera_position, 45.0, 0.1, 100.0, 0.3, 10.0, Some(0.9));
+    camera.look_at(scene.camera_target);
+    // writes the view projection into the uniform
+    camera.resize(&state);
+
+    let mut chunk = Chunk::new(glam::IVec3::ZERO);
+    let size = scene.blocks.size();
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                if scene.blocks.get(x, y, z) {
+                    chunk.set_block(x, y, z, scene.block);
+                }
+            }
+        }
+    }
+
+    let mut chunks = ChunkMap::default();
+    chunks.set_face_format(scene.face_format);
+    chunks.insert(chunk);
+    chunks.update(&state);
+    chunks.wait_for_meshes(&state).expect("Unable to mesh the golden scene");
+
+    let block_textures = BlockTextures::new(&state, &BlockRegistry::default(), TEXTURE_DIRECTORY);
+
+    let mut renderer = Renderer::new(
+        &state,
+        &[camera.bind_group_layout(), block_textures.bind_group_layout()],
+        vec![camera.create_bind_group(&state), block_textures.create_bind_group(&state)],
+        vec![],
+        &shader,
+        &ui_shader,
+        SAMPLE_COUNT,
+    );
+    assert!(
+        scene.face_format != FaceFormat::Packed || renderer.supports_packed_faces(),
+        "The adapter does not support packed faces",
+    );
+
+    renderer.set_pipeline_type(scene.pipeline_type);
+    renderer.render(&state, &chunks.meshes()).unwrap();
+
+    state.read_frame()
+        .expect("Headless states always have a frame to read")
+        .unwrap_or_else(|error| panic!("Unable to read back the frame: {}", error))
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name))
+}
+
+fn output_path(name: &str, suffix: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden").join(format!("{}.{}.png", name, suffix))
+}
+
+fn write_png(path: &Path, pixels: &[u8]) {
+    save_png(path, winit::dpi::PhysicalSize::new(WIDTH, HEIGHT), pixels).unwrap();
+}
+
+fn read_png(path: &Path) -> Option<Vec<u8>> {
+    let decoder = png::Decoder::new(std::fs::File::open(path).ok()?);
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+
+    assert_eq!((info.width, info.height), (WIDTH, HEIGHT), "{} has a different size", path.display());
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{} is not RGBA", path.display());
+
+    pixels.truncate(info.buffer_size());
+    Some(pixels)
+}
+
+/// Mismatched pixels are red, the others a faded copy of the reference.
+fn diff_image(expected: &[u8], actual: &[u8]) -> (Vec<u8>, usize) {
+    let mut diff = Vec::with_capacity(expected.len());
+    let mut mismatched = 0;
+
+    for (expected, actual) in expected.chunks(4).zip(actual.chunks(4)) {
+        let matches = expected.iter().zip(actual).all(|(a, b)| a.abs_diff(*b) <= TOLERANCE);
+
+        if matches {
+            let gray = ((expected[0] as u32 + expected[1] as u32 + expected[2] as u32) / 12) as u8;
+            diff.extend_from_slice(&[gray, gray, gray, 255]);
+        } else {
+            mismatched += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        }
+    }
+
+    (diff, mismatched)
+}
+
+fn check(name: &str, scene: Scene) {
+    check_against(name, name, scene);
+}
+
+/// Compares the scene with the reference image of another scene that has to look the same,
+/// the rendered image and the diff are still named after `name`.
+fn check_against(name: &str, reference: &str, scene: Scene) {
+    let actual = render(&scene);
+
+    let path = golden_path(reference);
+    let bless = std::env::var("GOLDEN_BLESS").is_ok_and(|value| value == "1");
+
+    let expected = match read_png(&path) {
+        Some(expected) if !bless => expected,
+        None if !bless => panic!("The reference image {} is missing, run with GOLDEN_BLESS=1 to create it", path.display()),
+        _ => {
+            write_png(&path, &actual);
+            eprintln!("Wrote the reference image {}", path.display());
+            return;
+        },
+    };
+
+    let (diff, mismatched) = diff_image(&expected, &actual);
+    if mismatched > 0 {
+        let actual_path = output_path(name, "actual");
+        let diff_path = output_path(name, "diff");
+        write_png(&actual_path, &actual);
+        write_png(&diff_path, &diff);
+
+        panic!(
+            "{} pixels of {} differ from {}, see {} and {}",
+            mismatched, name, path.display(), actual_path.display(), diff_path.display(),
+        );
+    }
+}
+
+
+fn single_block() -> Array3D {
+    let mut blocks = Array3D::new(4);
+    blocks.set(1, 1, 1, true);
+    blocks
+}
+
+/// Three steps going up along x, with a gap to check the culling between separate blocks.
+fn stairs() -> Array3D {
+    let mut blocks = Array3D::new(8);
+
+    for z in 1..4 {
+        for step in 0..3 {
+            for y in 0..=step {
+                blocks.set(1 + step, y, z, true);
+            }
+        }
+
+        blocks.set(6, 0, z, true);
+    }
+
+    blocks
+}
+
+#[test]
+fn golden_single_block() {
+    check("single_block", Scene {
+        blocks: single_block(),
+        block: 1,
+        camera_position: glam::vec3(4.0, 4.0, 5.0),
+        camera_target: glam::vec3(1.5, 1.5, 1.5),
+        pipeline_type: PipelineType::Triangle,
+        face_format: FaceFormat::Instanced,
+    });
+}
+
+#[test]
+fn golden_stairs() {
+    check("stairs", Scene {
+        blocks: stairs(),
+        block: 3,
+        camera_position: glam::vec3(-2.0, 6.0, 10.0),
+        camera_target: glam::vec3(4.0, 1.0, 2.5),
+        pipeline_type: PipelineType::Triangle,
+        face_format: FaceFormat::Instanced,
+    });
+}
+
+#[test]
+fn golden_stairs_wireframe() {
+    check("stairs_wireframe", Scene {
+        blocks: stairs(),
+        block: 3,
+        camera_position: glam::vec3(-2.0, 6.0, 10.0),
+        camera_target: glam::vec3(4.0, 1.0, 2.5),
+        pipeline_type: PipelineType::Line,
+        face_format: FaceFormat::Instanced,
+    });
+}
+
+/// Packed faces have to look exactly like the instanced ones.
+#[test]
+fn golden_stairs_packed() {
+    check_against("stairs_packed", "stairs", Scene {
+        blocks: stairs(),
+        block: 3,
+        camera_position: glam::vec3(-2.0, 6.0, 10.0),
+        camera_target: glam::vec3(4.0, 1.0, 2.5),
+        pipeline_type: PipelineType::Triangle,
+        face_format: FaceFormat::Packed,
+    });
+}